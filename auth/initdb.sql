@@ -91,4 +91,39 @@ $$ LANGUAGE plpgsql;
 CREATE TRIGGER trg_assign_permissions
 AFTER INSERT ON buckets
 FOR EACH ROW
-EXECUTE FUNCTION assign_bucket_on_users();
\ No newline at end of file
+EXECUTE FUNCTION assign_bucket_on_users();
+
+CREATE OR REPLACE FUNCTION notify_permission_change()
+RETURNS trigger AS $$
+BEGIN
+    IF TG_TABLE_NAME = 'users' THEN
+        PERFORM pg_notify('permissions_changed', json_build_object('user_id', OLD.id)::text);
+    ELSE
+        IF TG_OP IN ('UPDATE', 'DELETE') THEN
+            PERFORM pg_notify('permissions_changed', json_build_object(
+                'user_id', OLD.user_id,
+                'bucket', OLD.bucket
+            )::text);
+        END IF;
+
+        IF TG_OP IN ('INSERT', 'UPDATE') THEN
+            PERFORM pg_notify('permissions_changed', json_build_object(
+                'user_id', NEW.user_id,
+                'bucket', NEW.bucket
+            )::text);
+        END IF;
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_users_buckets_notif
+AFTER INSERT OR UPDATE OR DELETE ON users_buckets
+FOR EACH ROW
+EXECUTE FUNCTION notify_permission_change();
+
+CREATE TRIGGER trg_users_notif
+AFTER UPDATE OF role, user_state OR DELETE ON users
+FOR EACH ROW
+EXECUTE FUNCTION notify_permission_change();
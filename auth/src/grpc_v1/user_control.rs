@@ -2,33 +2,230 @@ mod proto {
     tonic::include_proto!("info");
 }
 
+use futures::Stream;
 pub use proto::{
-    AllowedBucketReply, BucketUser as BucketUserProto, UserByIdReq, UserReply,
+    AllowedBucketReply, BucketEvent, BucketOperation, BucketUser as BucketUserProto,
+    PermissionEvent, RegisterBucketReply, UserByIdReq, UserReply,
     info_server::{Info, InfoServer},
 };
-use std::sync::Arc;
+use serde::Deserialize;
+use std::{pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
 use tonic::{Response, Status, async_trait};
 use uuid::Uuid;
 
 use crate::{
-    grpc_v1::user_control::proto::{AllowedBucketReq, BucketReply, BucketReq},
-    models::{BucketUser, Permissions},
-    state::{PgRepository, QueryOwn, Types},
+    grpc_v1::user_control::proto::{
+        AllowedBucketReq, BucketReply, BucketReq, RegisterBucketReq, WatchBucketsReq,
+        WatchPermissionsReq,
+    },
+    models::{BucketUser, Permissions, bucket::Buckets},
+    state::{Insert, InsertOwn, PgRepository, QueryOwn, Types, error::RepositoryError},
 };
 
+const BUCKET_CHANNEL: &str = "bucket_changed";
+const PERMISSIONS_CHANNEL: &str = "permissions_changed";
+
 #[derive(Debug)]
 pub struct InfoUserProgram {
     repo: Arc<PgRepository>,
+    events: broadcast::Sender<PermissionEvent>,
+    buckets: broadcast::Sender<BucketEvent>,
 }
 
 impl InfoUserProgram {
     pub fn new(repo: Arc<PgRepository>) -> Self {
-        Self { repo }
+        let (events, _) = broadcast::channel(256);
+        let (buckets, _) = broadcast::channel(256);
+        Self {
+            repo,
+            events,
+            buckets,
+        }
+    }
+
+    pub fn listen_changes(&self) {
+        let repo = self.repo.clone();
+        let events = self.events.clone();
+        let buckets = self.buckets.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let mut listener = match repo.listener(&[BUCKET_CHANNEL, PERMISSIONS_CHANNEL]).await
+                {
+                    Ok(listener) => listener,
+                    Err(er) => {
+                        tracing::error!("[ InfoUserProgram ] listen error: {er}");
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
+                tracing::info!("[ InfoUserProgram ] Listening permission changes");
+                _ = events.send(PermissionEvent::default());
+                _ = buckets.send(BucketEvent::resync());
+
+                loop {
+                    match listener.try_recv().await {
+                        Ok(Some(notification)) => {
+                            match serde_json::from_str::<ChangeNotification>(notification.payload())
+                            {
+                                Ok(change) => {
+                                    if let Some(ev) = change.bucket_event() {
+                                        _ = buckets.send(ev);
+                                    }
+                                    for ev in change.events() {
+                                        _ = events.send(ev);
+                                    }
+                                }
+                                Err(er) => tracing::error!(
+                                    "[ InfoUserProgram ] invalid payload on {}: {er}",
+                                    notification.channel()
+                                ),
+                            }
+                        }
+                        Ok(None) => {
+                            tracing::warn!("[ InfoUserProgram ] listener reconnected");
+                            _ = events.send(PermissionEvent::default());
+                            _ = buckets.send(BucketEvent::resync());
+                        }
+                        Err(er) => {
+                            tracing::error!("[ InfoUserProgram ] listener error: {er}");
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[derive(Debug, Deserialize)]
+enum Operation {
+    New,
+    Rename,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangeNotification {
+    operation: Option<Operation>,
+    user_id: Option<Uuid>,
+    bucket: Option<String>,
+    old_bucket: Option<String>,
+}
+
+impl ChangeNotification {
+    fn bucket_event(&self) -> Option<BucketEvent> {
+        let operation = match self.operation.as_ref()? {
+            Operation::New => BucketOperation::New,
+            Operation::Rename => BucketOperation::Rename,
+            Operation::Delete => BucketOperation::Delete,
+        };
+
+        Some(BucketEvent {
+            operation: operation as i32,
+            bucket: self.bucket.clone()?,
+            old_bucket: self.old_bucket.clone(),
+        })
+    }
+
+    fn events(self) -> Vec<PermissionEvent> {
+        let user_id = self.user_id.map(|x| x.as_bytes().to_vec());
+        let mut events = vec![PermissionEvent {
+            user_id: user_id.clone(),
+            bucket: self.bucket,
+        }];
+        if let Some(old_bucket) = self.old_bucket {
+            events.push(PermissionEvent {
+                user_id,
+                bucket: Some(old_bucket),
+            });
+        }
+        events
+    }
+}
+
+impl BucketEvent {
+    fn resync() -> Self {
+        Self {
+            operation: BucketOperation::Resync as i32,
+            bucket: String::new(),
+            old_bucket: None,
+        }
     }
 }
 
 #[async_trait]
 impl Info for InfoUserProgram {
+    type WatchBucketsStream =
+        Pin<Box<dyn Stream<Item = Result<BucketEvent, Status>> + Send + 'static>>;
+
+    async fn watch_buckets(
+        &self,
+        _request: tonic::Request<WatchBucketsReq>,
+    ) -> Result<Response<Self::WatchBucketsStream>, Status> {
+        let rx = self.buckets.subscribe();
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            match rx.recv().await {
+                Ok(ev) => Some((Ok(ev), rx)),
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("[ InfoUserProgram ] bucket watcher lagged {n} events");
+                    Some((Ok(BucketEvent::resync()), rx))
+                }
+                Err(RecvError::Closed) => None,
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn register_bucket(
+        &self,
+        request: tonic::Request<RegisterBucketReq>,
+    ) -> Result<Response<RegisterBucketReply>, Status> {
+        let RegisterBucketReq { name } = request.into_inner();
+
+        let created = match self
+            .repo
+            .insert(InsertOwn::insert(Buckets {
+                name: name.clone(),
+                description: None,
+            }))
+            .await
+        {
+            Ok(_) => true,
+            Err(RepositoryError::AlreadyExist(_)) => false,
+            Err(er) => {
+                tracing::error!("[ InfoUserProgram ] register bucket {name} error: {er}");
+                return Err(Status::internal(er.to_string()));
+            }
+        };
+
+        Ok(Response::new(RegisterBucketReply { created }))
+    }
+
+    type WatchPermissionsStream =
+        Pin<Box<dyn Stream<Item = Result<PermissionEvent, Status>> + Send + 'static>>;
+
+    async fn watch_permissions(
+        &self,
+        _request: tonic::Request<WatchPermissionsReq>,
+    ) -> Result<Response<Self::WatchPermissionsStream>, Status> {
+        let rx = self.events.subscribe();
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            match rx.recv().await {
+                Ok(ev) => Some((Ok(ev), rx)),
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("[ InfoUserProgram ] watcher lagged {n} events");
+                    Some((Ok(PermissionEvent::default()), rx))
+                }
+                Err(RecvError::Closed) => None,
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
     async fn user_by_id(
         &self,
         request: tonic::Request<UserByIdReq>,
@@ -89,6 +286,26 @@ impl Info for InfoUserProgram {
         let resp = self.repo.gets(qr).await.unwrap_or_default();
         Ok(Response::new(resp.into()))
     }
+
+    async fn list_buckets(
+        &self,
+        request: tonic::Request<BucketReq>,
+    ) -> Result<Response<BucketReply>, Status> {
+        let req = request.into_inner();
+        let qr = req.name.map_or(QueryOwn::<Buckets>::builder(), |q| {
+            QueryOwn::<Buckets>::builder().wh("name", q)
+        });
+
+        match self.repo.gets(qr).await {
+            Ok(buckets) => Ok(Response::new(BucketReply {
+                buckets: buckets.into_iter().map(|x| x.name).collect(),
+            })),
+            Err(er) => {
+                tracing::error!("[ InfoUserProgram ] list buckets error: {er}");
+                Err(Status::internal(er.to_string()))
+            }
+        }
+    }
 }
 
 impl From<proto::Permissions> for Permissions {
@@ -57,6 +57,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     JwtHandle::gen_ecdsa(None)?;
     let gprc_ceck_user = "[::]:50051".parse()?;
     let user_check = InfoUserProgram::new(repo.clone());
+    user_check.listen_changes();
 
     tracing::info!("Listening {}:{}", app_listen, app_port);
 
@@ -27,7 +27,7 @@ impl Table for Buckets {
     }
 
     fn columns() -> &'static [&'static str] {
-        &["id", "icon", "user_id", "name", "description"]
+        &["name", "description"]
     }
 
     fn values(self) -> Self::ValuesOutput {
@@ -6,7 +6,7 @@ use serde::Serialize;
 use serde_json::json;
 use sqlx::{
     pool::Pool,
-    postgres::{PgPoolOptions, PgRow, Postgres},
+    postgres::{PgListener, PgPoolOptions, PgRow, Postgres},
 };
 use uuid::Uuid;
 
@@ -39,6 +39,12 @@ impl PgRepository {
         Ok(repo)
     }
 
+    pub async fn listener(&self, channels: &[&str]) -> Result<PgListener, RepositoryError> {
+        let mut listener = PgListener::connect_with(&self.inner).await?;
+        listener.listen_all(channels.iter().copied()).await?;
+        Ok(listener)
+    }
+
     pub async fn get<T>(&self, mut query: QueryOwn<'_, T>) -> Result<T, RepositoryError>
     where
         T: QuerySelect + From<PgRow>,
@@ -1,41 +1,53 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     path::{Path, PathBuf},
     pin::Pin,
+    sync::{Arc, Mutex},
 };
 
-use futures::{FutureExt, StreamExt, TryStreamExt};
-use hyper_tungstenite::HyperWebsocket;
-use mongodb::bson::{Document, doc, oid::ObjectId};
+use futures::{FutureExt, future::join_all};
+use tokio::sync::{OwnedRwLockReadGuard, RwLock, Semaphore};
 
 use crate::{
     actor::Actor,
     bucket::{
-        self, Bucket, Cowed,
+        Bucket, Cowed,
         fhs::Fhs,
         key::{Key, Segment},
         object::Object,
+        objects::{ObjectIndexes, Objects},
+        progress::BuildProgress,
+        search::{Duplicate, SearchIndex, SearchPage, SearchQuery},
+        snapshot::{Fingerprint, Snapshot, SnapshotEntry},
+        usage::Usage,
         utils::{
             Rename, RenameDecision, list_buckets_and_normalize,
             normalizeds::{NormalizeFileUtf8, NormalizePathUtf8},
         },
     },
-    manager::{
-        Change,
-        websocket::{WebSocketHandler, broker::WSBroker},
-    },
-    state::local_storage::{AsObjectDeserialize, COLLECTION, LocalStorage},
+    manager::{Change, ObjectLocation, websocket::broker::WSBroker},
+    state::local_storage::MetadataStore,
 };
 
+pub type SharedEntry = Arc<RwLock<KeyEntry>>;
+
 pub struct BucketMap {
     path: PathBuf,
-    pub tree: BTreeMap<Bucket<'static>, KeyEntry>,
+    tree: std::sync::RwLock<BTreeMap<Bucket<'static>, SharedEntry>>,
     broker: <WSBroker as Actor>::ActorRef,
+    verify_checksums: bool,
+    hash_concurrency: usize,
+    db_concurrency: usize,
+    indexes: ObjectIndexes,
+    search: RwLock<SearchIndex>,
 }
 
 pub struct KeyEntry {
-    pub objects: Option<Vec<Object>>,
+    pub objects: Option<Objects>,
     pub keys: Option<BTreeMap<Segment<'static>, KeyEntry>>,
+    pub pending: Option<BTreeSet<String>>,
+    pub fingerprint: Option<Fingerprint>,
+    pub usage: Usage,
     pub broker: <WSBroker as Actor>::ActorRef,
 }
 
@@ -53,6 +65,11 @@ impl BucketMap {
             path,
             tree: Default::default(),
             broker: WSBroker::default().start(),
+            verify_checksums: false,
+            hash_concurrency: std::thread::available_parallelism().map_or(1, |x| x.get()),
+            db_concurrency: 16,
+            indexes: ObjectIndexes::default(),
+            search: Default::default(),
         }
     }
 
@@ -60,350 +77,497 @@ impl BucketMap {
         &self.path
     }
 
-    pub fn get_object<'a>(
-        &'a self,
-        bucket: &'a Bucket<'_>,
-        key: &'a Key<'_>,
-        file_name: &'a str,
-    ) -> Option<&'a Object> {
-        self.get_entry(bucket, key).and_then(|v| {
-            v.objects
-                .as_ref()
-                .and_then(|x| x.iter().find(|x| x.file_name == file_name))
-        })
+    pub fn set_verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
     }
 
-    pub fn get_buckets<'a>(&'a self) -> impl IntoIterator<Item = &'a Bucket<'a>> {
-        self.tree.keys()
+    pub fn set_build_concurrency(&mut self, hash: usize, db: usize) {
+        if hash > 0 {
+            self.hash_concurrency = hash;
+        }
+        if db > 0 {
+            self.db_concurrency = db;
+        }
     }
 
-    pub fn get_entry<'a>(
-        &'a self,
-        bucket: &'a Bucket<'_>,
-        key: &'a Key<'_>,
-    ) -> Option<&'a KeyEntry> {
-        if key.is_root() {
-            self.tree.get(bucket)
-        } else {
-            key.into_iter()
-                .try_fold(self.tree.get(&bucket)?, |entry, x| {
-                    entry.keys.as_ref()?.get(&x)
-                })
-        }
+    pub fn set_object_indexes(&mut self, indexes: ObjectIndexes) {
+        self.indexes = indexes;
     }
 
-    pub fn get_mut_entry<'a>(
-        &'a mut self,
-        bucket: &'a Bucket<'static>,
-        key: &'a Key<'_>,
-    ) -> Option<&'a mut KeyEntry> {
-        if key.is_root() {
-            self.tree.get_mut(bucket)
-        } else {
-            let mut entry = self.tree.get_mut(&bucket)?;
-            let keys = key.into_iter().map(|x| x.owned()).collect::<Vec<_>>();
+    pub fn verify_checksums(&self) -> bool {
+        self.verify_checksums
+    }
 
-            for key in keys {
-                entry = entry.keys.as_mut().and_then(|x| x.get_mut(&key))?;
+    pub fn bucket(&self, bucket: &Bucket<'_>) -> Option<SharedEntry> {
+        self.tree.read().unwrap().get(bucket).cloned()
+    }
+
+    pub fn buckets(&self) -> Vec<(Bucket<'static>, SharedEntry)> {
+        self.tree
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(bucket, entry)| (bucket.cloned(), entry.clone()))
+            .collect()
+    }
+
+    pub fn get_buckets(&self) -> Vec<Bucket<'static>> {
+        self.tree
+            .read()
+            .unwrap()
+            .keys()
+            .map(Cowed::cloned)
+            .collect()
+    }
+
+    pub async fn read_bucket(&self, bucket: &Bucket<'_>) -> Option<OwnedRwLockReadGuard<KeyEntry>> {
+        Some(self.bucket(bucket)?.read_owned().await)
+    }
+
+    pub async fn contains(&self, bucket: &Bucket<'_>, key: &Key<'_>) -> bool {
+        self.read_bucket(bucket)
+            .await
+            .is_some_and(|x| x.get(key).is_some())
+    }
+
+    pub async fn get_object(
+        &self,
+        bucket: &Bucket<'_>,
+        key: &Key<'_>,
+        file_name: &str,
+    ) -> Option<Object> {
+        self.read_bucket(bucket).await.and_then(|x| {
+            x.get(key)
+                .and_then(|x| x.objects.as_ref())
+                .and_then(|x| x.get(file_name))
+                .cloned()
+        })
+    }
+
+    pub async fn brokers(
+        &self,
+        bucket: Option<&Bucket<'_>>,
+        key: Option<&Key<'_>>,
+    ) -> Vec<<WSBroker as Actor>::ActorRef> {
+        let mut brokers = vec![self.broker.clone()];
+
+        let Some(root) = bucket.and_then(|x| self.bucket(x)) else {
+            return brokers;
+        };
+        let root = root.read().await;
+        let mut entry = &*root;
+        brokers.push(entry.broker.clone());
+
+        if let Some(key) = key.filter(|x| !x.is_root()) {
+            for seg in key {
+                let Some(child) = entry.keys.as_ref().and_then(|x| x.get(&seg)) else {
+                    break;
+                };
+                brokers.push(child.broker.clone());
+                entry = child;
             }
+        }
 
-            Some(entry)
+        brokers
+    }
+
+    pub async fn subtree_brokers(
+        &self,
+        bucket: &Bucket<'_>,
+        key: Option<&Key<'_>>,
+    ) -> Vec<<WSBroker as Actor>::ActorRef> {
+        let mut brokers = Vec::new();
+        if let Some(root) = self.read_bucket(bucket).await
+            && let Some(entry) = root.get(key.unwrap_or(&Key::root()))
+        {
+            entry.collect_brokers(&mut brokers);
         }
+        brokers
     }
 
-    pub async fn subscriber(
-        &mut self,
-        bucket: Option<Bucket<'_>>,
-        key: Option<Key<'_>>,
-        ws: HyperWebsocket,
-    ) {
-        match ws.await.map(|x| x.split()) {
-            Ok((tx, mut rx)) => {
-                let broker = if let Some(bucket) = bucket {
-                    let key = key.unwrap_or(Key::root());
-                    match self.get_entry(&bucket, &key) {
-                        Some(entry) => entry.broker.clone(),
-                        None => {
-                            tracing::error!(
-                                "[ BucketMap ] Subscriber error, entry not found: bucket: {bucket} - key: {key}"
-                            );
-                            return;
-                        }
-                    }
-                } else {
-                    self.broker.clone()
-                };
+    pub async fn broker(
+        &self,
+        bucket: Option<&Bucket<'_>>,
+        key: Option<&Key<'_>>,
+    ) -> Option<<WSBroker as Actor>::ActorRef> {
+        match bucket {
+            Some(bucket) => self
+                .read_bucket(bucket)
+                .await?
+                .get(key.unwrap_or(&Key::root()))
+                .map(|x| x.broker.clone()),
+            None => Some(self.broker.clone()),
+        }
+    }
 
-                let actor_ref = WebSocketHandler { user: tx, broker }.start();
-
-                tokio::spawn(async move {
-                    loop {
-                        match rx.next().await {
-                            Some(Ok(msg)) => {
-                                tracing::info!(
-                                    "[ WebSocketPeer from Subscriber BucketMap ]: {msg}"
-                                );
-                            }
-                            Some(Err(er)) => {
-                                tracing::error!(
-                                    "[ WebSocketPeer from Subscriber BucketMap ] error {er}"
-                                );
-                            }
-                            None => {
-                                actor_ref.shutdown().await;
-                                break;
-                            }
-                        }
-                    }
-                });
-            }
-            Err(er) => {
-                tracing::error!("[ BucketMap ] subscriber error: {er}");
-            }
+    pub async fn search(
+        &self,
+        query: &SearchQuery,
+        allowed: impl Fn(&Bucket<'_>) -> bool,
+    ) -> SearchPage {
+        self.search.read().await.search(query, allowed)
+    }
+
+    pub async fn locate(
+        &self,
+        checksum: &str,
+        allowed: impl Fn(&Bucket<'_>) -> bool,
+    ) -> Vec<ObjectLocation> {
+        self.search.read().await.locate(checksum, allowed)
+    }
+
+    pub async fn duplicates(&self, allowed: impl Fn(&Bucket<'_>) -> bool) -> Vec<Duplicate> {
+        self.search.read().await.duplicates(allowed)
+    }
+
+    pub async fn change(&self, change: Change) {
+        let indexed = change.clone();
+        if self.apply(change).await {
+            self.search.write().await.apply(&indexed);
         }
     }
 
-    pub async fn change(&mut self, change: Change) {
+    async fn apply(&self, change: Change) -> bool {
         match change {
-            Change::NewObject {
-                bucket,
-                key,
-                object,
-            } => {
-                let Some(entry) = self.get_mut_entry(&bucket, &key) else {
-                    return;
-                };
-
-                entry.objects.get_or_insert_default().push(object);
-            }
-            Change::NewKey { bucket, key } => {
-                let key = key.inner();
-                if let Some((key, new_key)) = key
-                    .rsplit_once("/")
-                    .map(|(k, nk)| (Key::new(k), Segment::new(nk)))
-                {
-                    if let Some(entry) = self.get_mut_entry(&bucket, &key) {
-                        entry
-                            .keys
-                            .as_mut()
-                            .unwrap()
-                            .entry(new_key.owned())
-                            .or_default();
-                    } else {
-                        tracing::debug!(
-                            "[ BucketMap ] New key, parent key {} not found in bucket {}",
-                            key,
-                            bucket
-                        );
-                    }
-                } else {
-                    self.tree.get_mut(&bucket).map(|x| {
-                        x.keys
-                            .as_mut()
-                            .unwrap()
-                            .entry(Segment::new(key))
-                            .or_default()
-                    });
-                }
-            }
             Change::NewBucket { bucket } => {
-                self.tree.entry(bucket).or_default();
-            }
-            Change::NameObject {
-                bucket,
-                key,
-                from,
-                to,
-            } => {
-                if let Some(entry) = self.get_mut_entry(&bucket, &key) {
-                    if let Some(object) = entry
-                        .objects
-                        .as_mut()
-                        .and_then(|x| x.iter_mut().find(|x| x.file_name == from))
-                    {
-                        tracing::debug!(
-                            "[ BucketMap ] Rename object, from {} to {}, in {}/{}",
-                            from,
-                            to,
-                            bucket,
-                            key
-                        );
-                        object.file_name = to;
-                    } else {
-                        tracing::debug!(
-                            "[ BucketMap ] Rename object, object {} not found, in {}/{}",
-                            from,
-                            bucket,
-                            key
-                        );
-                    }
-                }
+                self.tree.write().unwrap().entry(bucket).or_default();
+                true
             }
             Change::NameBucket { from, to } => {
-                if self.tree.get(&to).is_some() {
+                let mut tree = self.tree.write().unwrap();
+                if tree.contains_key(&to) {
                     tracing::error!("[ BucketMap ] RenameBucket; bucket {} already exists", to);
                 }
 
-                if let Some(entry) = self.tree.remove(&from) {
-                    self.tree.insert(to, entry);
+                if let Some(entry) = tree.remove(&from) {
+                    tree.insert(to, entry);
+                    true
                 } else {
                     tracing::error!("[ BucketMap ] RenameBucket; bucket {} not found", from);
+                    false
                 }
             }
-            Change::NameKey { bucket, from, to } => {
-                if let Some((parent, from_seg)) = from
-                    .name()
-                    .rsplit_once('/')
-                    .map(|(key, seg)| (Key::new(key).owned(), Segment::new(seg).owned()))
+            Change::DeleteBucket { bucket } => {
+                let removed = self.tree.write().unwrap().remove(&bucket);
+                if let Some(bk) = removed {
+                    tracing::info!(
+                        "[ BucketMap ] deleted: {:#?}",
+                        Fhs::create_branch(Some(bucket.into()), &*bk.read().await)
+                    );
+                    true
+                } else {
+                    tracing::error!("[ BucketMap ] bucket {bucket} not found");
+                    false
+                }
+            }
+            Change::MoveObject { from, to } => {
+                let (Some(source), Some(target)) =
+                    (self.bucket(&from.bucket), self.bucket(&to.bucket))
+                else {
+                    tracing::error!(
+                        "[ BucketMap ] MoveObject; bucket {} or {} not found",
+                        from.bucket,
+                        to.bucket
+                    );
+                    return false;
+                };
+
+                let mut source_root = source.write().await;
+                let mut target_root = match Arc::ptr_eq(&source, &target) {
+                    true => None,
+                    false => Some(target.write().await),
+                };
+
+                if target_root
+                    .as_deref()
+                    .unwrap_or(&source_root)
+                    .get(&to.key)
+                    .is_none()
                 {
-                    let Some(entry) = self.get_mut_entry(&bucket, &parent) else {
-                        tracing::error!("[ Bucket Map ] Key {from} not found");
-                        return;
-                    };
+                    tracing::error!(
+                        "[ BucketMap ] MoveObject; destination {}/{} not found",
+                        to.bucket,
+                        to.key
+                    );
+                    return false;
+                }
 
-                    let keys = entry.keys.as_mut().unwrap();
+                let Some(entry) = source_root.get_mut(&from.key) else {
+                    tracing::error!(
+                        "[ BucketMap ] MoveObject; source {}/{} not found",
+                        from.bucket,
+                        from.key
+                    );
+                    return false;
+                };
+
+                let in_progress = entry.settle(&from.file_name);
+                let object = entry
+                    .objects
+                    .as_mut()
+                    .and_then(|x| x.remove(&from.file_name));
 
-                    if keys.get(&to).is_some() {
+                let entry = target_root
+                    .as_deref_mut()
+                    .unwrap_or(&mut source_root)
+                    .get_mut(&to.key)
+                    .unwrap();
+                let moved = match object {
+                    Some(mut object) => {
+                        object.file_name = to.file_name;
+                        entry.objects_mut(self.indexes).insert(object);
+                        true
+                    }
+                    None if in_progress => {
+                        entry.pending.get_or_insert_default().insert(to.file_name);
+                        true
+                    }
+                    None => {
                         tracing::error!(
-                            "[ BucketMap ] Key {parent}/{to} already exists, i cannot rename the key {from}"
+                            "[ BucketMap ] MoveObject; object {} not found in {}/{}",
+                            from.file_name,
+                            from.bucket,
+                            from.key
                         );
-                        return;
+                        false
                     }
+                };
 
-                    let old = keys.remove(&from_seg);
-                    keys.insert(to, old.unwrap_or_default());
-                } else {
-                    let Some(entry) = self.tree.get_mut(&bucket) else {
-                        tracing::error!("[ BucketMap ] Bucket not found {bucket}");
-                        return;
-                    };
-
-                    let keys = entry.keys.as_mut().unwrap();
-                    let from = from.into();
-                    if keys.get(&to).is_some() {
-                        tracing::error!("[ BucketMap ] {to} already exists");
-                        return;
-                    };
-
-                    let old = keys.remove(&from);
-
-                    keys.insert(to, old.unwrap_or_default());
-                }
+                source_root.refresh(&from.key);
+                target_root
+                    .as_deref_mut()
+                    .unwrap_or(&mut source_root)
+                    .refresh(&to.key);
+                moved
             }
-            Change::DeleteObject {
-                bucket,
-                key,
-                file_name,
-            } => {
-                if let Some(entry) = self.get_mut_entry(&bucket, &key) {
-                    let Some(objs) = entry.objects.as_mut() else {
-                        tracing::error!("[ BucketMap ] Delete Object: I haven't objects in {key}");
-                        return;
-                    };
+            Change::MoveKey { from, to } => {
+                let Some(entry) = self.take_entry(&from.bucket, &from.key).await else {
+                    tracing::error!(
+                        "[ BucketMap ] MoveKey; source {}/{} not found",
+                        from.bucket,
+                        from.key
+                    );
+                    return false;
+                };
 
-                    if let Some(idx) = objs.iter().position(|x| x.file_name == file_name) {
-                        objs.swap_remove(idx);
-                        tracing::debug!("[ BucketMap ] object {file_name} deleted from key {key}");
-                    } else {
-                        tracing::error!("[ BucketMap ] object {file_name} not found in key {key}");
-                    }
-                } else {
-                    tracing::error!("[ BucketMap ] The bucket {bucket} with key {key} not found");
+                if let Err(entry) = self.put_entry(&to.bucket, &to.key, entry).await {
+                    tracing::error!(
+                        "[ BucketMap ] MoveKey; destination {}/{} unavailable",
+                        to.bucket,
+                        to.key
+                    );
+                    _ = self.put_entry(&from.bucket, &from.key, *entry).await;
+                    return false;
                 }
+                true
             }
-            Change::DeleteKey { bucket, key } => {
-                if let Some((parent, to_delete)) = key
-                    .name()
-                    .rsplit_once('/')
-                    .map(|(p, s)| (Key::new(p.to_string()), Segment::new(s.to_string())))
-                {
-                    let Some(entry) = self.get_mut_entry(&bucket, &parent) else {
-                        return;
-                    };
-
-                    let keys = entry.keys.as_mut().unwrap();
-                    if let Some(entry) = keys.remove(&to_delete) {
-                        tracing::info!(
-                            "[ BucketMap ] from bucket {bucket} delete: {:#?}",
-                            Fhs::create_branch(Some((&bucket).into()), &entry)
-                        );
-                    } else {
-                        tracing::error!("[ BucketMap ] Key {key} not found in bucket {bucket}");
-                    }
-                } else {
-                    let Some(entry) = self.tree.get_mut(&bucket) else {
-                        return;
-                    };
-                    let seg = key.into();
-                    if entry.keys.as_mut().unwrap().remove(&seg).is_some() {
-                        tracing::info!("[ BucketMap ] {seg} deleted from {bucket}");
-                    } else {
-                        tracing::error!("[ BucketMap ] {seg} not found in bucket {bucket}");
-                    }
-                }
+            Change::Resync { .. } => true,
+            change => {
+                let Some(bucket) = change.location().0.and_then(|x| self.bucket(x)) else {
+                    tracing::error!("[ BucketMap ] Bucket not found for {change:?}");
+                    return false;
+                };
+                bucket.write().await.change(change, self.indexes)
             }
-            Change::DeleteBucket { bucket } => {
-                if let Some(bk) = self.tree.remove(&bucket) {
-                    tracing::info!(
-                        "[ BucketMap ] deleted: {:#?}",
-                        Fhs::create_branch(Some(bucket.into()), &bk)
-                    );
-                } else {
-                    tracing::error!("[ BucketMap ] bucket {bucket} not found");
-                }
+        }
+    }
+
+    async fn take_entry(&self, bucket: &Bucket<'static>, key: &Key<'_>) -> Option<KeyEntry> {
+        if key.is_root() {
+            let removed = self.tree.write().unwrap().remove(bucket)?;
+            return Some(match Arc::try_unwrap(removed) {
+                Ok(entry) => entry.into_inner(),
+                Err(shared) => std::mem::take(&mut *shared.write().await),
+            });
+        }
+
+        self.bucket(bucket)?.write().await.take(key)
+    }
+
+    async fn put_entry(
+        &self,
+        bucket: &Bucket<'static>,
+        key: &Key<'_>,
+        entry: KeyEntry,
+    ) -> Result<(), Box<KeyEntry>> {
+        if key.is_root() {
+            let mut tree = self.tree.write().unwrap();
+            if tree.contains_key(bucket) {
+                return Err(Box::new(entry));
             }
+            tree.insert(bucket.cloned(), Arc::new(RwLock::new(entry)));
+            return Ok(());
         }
+
+        let Some(shared) = self.bucket(bucket) else {
+            return Err(Box::new(entry));
+        };
+        shared.write().await.put(key, entry)
     }
 
-    pub async fn build(&mut self, ls: &LocalStorage) {
+    pub async fn build(
+        &self,
+        ls: &dyn MetadataStore,
+        snapshot: Option<Snapshot>,
+        progress: &BuildProgress,
+    ) {
         let buckets = list_buckets_and_normalize(&self.path);
-        let mut object_ids = Vec::new();
-        let mut inner = BTreeMap::new();
-        tracing::info!("[ BucketMap ] Build");
-        for (bucket, bucket_path) in buckets {
-            let entry = build_key_entry(&bucket_path, &bucket, &mut object_ids, ls).await;
-            inner.insert(bucket, entry);
-        }
+        let mut cached = snapshot
+            .filter(|_| !self.verify_checksums)
+            .map(|x| x.buckets)
+            .unwrap_or_default();
+        let scan = Scan {
+            ls,
+            verify: self.verify_checksums,
+            indexes: self.indexes,
+            hash: Semaphore::new(self.hash_concurrency.max(1)),
+            db: Semaphore::new(self.db_concurrency.max(1)),
+            progress,
+            seen: Mutex::new(Vec::new()),
+        };
+        tracing::info!(
+            "[ BucketMap ] Build, hash concurrency: {}, db concurrency: {}",
+            self.hash_concurrency,
+            self.db_concurrency
+        );
+
+        let inner = join_all(buckets.into_iter().map(|(bucket, bucket_path)| {
+            let cached = cached.remove(&bucket);
+            let scan = &scan;
+            async move {
+                let entry = build_key_entry(&bucket_path, &bucket, cached, scan).await;
+                (bucket, entry)
+            }
+        }))
+        .await
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
         tracing::debug!("[ BucketMap ] Build: {:#?}", inner);
-        self.tree = inner;
+        let mut search = SearchIndex::default();
+        for (bucket, entry) in &inner {
+            search.add_entry(bucket, &Key::root(), entry);
+        }
+        *self.search.write().await = search;
+        *self.tree.write().unwrap() = inner
+            .into_iter()
+            .map(|(bucket, entry)| (bucket, Arc::new(RwLock::new(entry))))
+            .collect();
+
+        sync_object_with_database(ls, scan.seen.into_inner().unwrap()).await;
+    }
+}
+
+fn split_key(key: &Key<'_>) -> (Key<'static>, Segment<'static>) {
+    match key.name().rsplit_once('/') {
+        Some((parent, seg)) => (Key::new(parent.to_string()), Segment::new(seg.to_string())),
+        None => (Key::root(), Segment::new(key.name().to_string())),
+    }
+}
 
-        sync_object_with_database(ls, object_ids).await;
+struct Scan<'a> {
+    ls: &'a dyn MetadataStore,
+    verify: bool,
+    indexes: ObjectIndexes,
+    hash: Semaphore,
+    db: Semaphore,
+    progress: &'a BuildProgress,
+    seen: Mutex<Vec<ObjectLocation>>,
+}
+
+impl Scan<'_> {
+    async fn hash(&self, path: &Path) -> Object {
+        let _permit = self.hash.acquire().await.unwrap();
+        let object = Object::new(path, Default::default()).await;
+        self.progress.hashed();
+        object
+    }
+
+    fn seen(&self, bucket: &Bucket<'_>, key: &Key<'_>, file_name: &str) {
+        self.seen.lock().unwrap().push(ObjectLocation {
+            bucket: bucket.cloned(),
+            key: key.cloned(),
+            file_name: file_name.to_string(),
+        });
     }
 }
 
 async fn sync_objects(
-    vec: Vec<PathBuf>,
+    vec: Vec<(PathBuf, String)>,
     bucket: Bucket<'_>,
     key: Key<'_>,
-    local_storage: &LocalStorage,
-    objects_ids: &mut Vec<ObjectId>,
+    scan: &Scan<'_>,
 ) -> Vec<Object> {
-    let mut resp = Vec::new();
-    for path in vec {
-        if let Some(name) = path.file_name().and_then(|x| x.to_str())
-            && let Ok(Some(object)) = local_storage
-                .get_object_filename(bucket.borrow(), key.borrow(), name)
-                .await
+    if vec.is_empty() {
+        return Vec::new();
+    }
+    scan.progress.objects(vec.len());
+
+    let names = vec.iter().map(|(_, name)| name.clone()).collect::<Vec<_>>();
+    let mut stored = {
+        let _permit = scan.db.acquire().await.unwrap();
+        match scan
+            .ls
+            .get_objects(bucket.borrow(), key.borrow(), &names)
+            .await
         {
-            tracing::info!(
-                "[ fn_sync_object ] {{ Object found on db (Method::name) }} object: {object:?}"
-            );
-            objects_ids.push(object._id.unwrap());
-            resp.push(object.object);
-        } else {
-            let obj = Object::new(path, Default::default()).await;
+            Ok(objects) => objects
+                .into_iter()
+                .map(|x| (x.file_name.clone(), x))
+                .collect::<HashMap<_, _>>(),
+            Err(er) => {
+                tracing::error!("[ fn_sync_object ] {er}");
+                HashMap::new()
+            }
+        }
+    };
 
-            if let Err(er) = local_storage
-                .new_object(bucket.borrow(), key.borrow(), &obj)
-                .await
-            {
-                tracing::error!("{er}");
+    let objects = join_all(vec.into_iter().map(|(path, name)| {
+        let stored = stored.remove(&name);
+        let (bucket, key) = (bucket.borrow(), key.borrow());
+        async move {
+            scan.seen(&bucket, &key, &name);
+            match stored {
+                Some(object) if !scan.verify && object.unchanged(&path) => {
+                    tracing::info!(
+                        "[ fn_sync_object ] {{ Object found on db (Method::name) }} object: {object:?}"
+                    );
+                    (object, false)
+                }
+                Some(_) => {
+                    tracing::info!(
+                        "[ fn_sync_object ] {{ Object changed on disk }} path: {path:?}"
+                    );
+                    let mut object = scan.hash(&path).await;
+                    let _permit = scan.db.acquire().await.unwrap();
+                    if let Err(er) = scan.ls.sync_object(bucket, key, &mut object).await {
+                        tracing::error!("{er}");
+                    }
+                    (object, false)
+                }
+                None => (scan.hash(&path).await, true),
             }
-            resp.push(obj);
+        }
+    }))
+    .await;
+
+    let new = objects
+        .iter()
+        .filter(|(_, new)| *new)
+        .map(|(object, _)| object.clone())
+        .collect::<Vec<_>>();
+
+    if !new.is_empty() {
+        let _permit = scan.db.acquire().await.unwrap();
+        if let Err(er) = scan
+            .ls
+            .new_objects(bucket.borrow(), key.borrow(), &new)
+            .await
+        {
+            tracing::error!("{er}");
         }
     }
 
-    resp
+    objects.into_iter().map(|(object, _)| object).collect()
 }
 
 async fn file_name_normalize(path: PathBuf) -> Option<(PathBuf, String)> {
@@ -441,77 +605,454 @@ async fn file_name_normalize(path: PathBuf) -> Option<(PathBuf, String)> {
 fn build_key_entry<'a>(
     path: &'a Path,
     bucket: &'a Bucket<'_>,
-    objects_ids: &'a mut Vec<ObjectId>,
-    local_storage: &'a LocalStorage,
+    cached: Option<SnapshotEntry>,
+    scan: &'a Scan<'_>,
 ) -> Pin<Box<dyn Future<Output = KeyEntry> + Send + 'a>> {
     async move {
-        let mut objects = Vec::new();
-        let mut keys = BTreeMap::new();
-        let mut read_dir = path.read_dir().unwrap().into_iter();
-
-        while let Some(entry) = read_dir.next().and_then(|x| x.ok().map(|x| x.path())) {
-            let Some((entry, file_name)) = file_name_normalize(entry).await else {
-                continue;
-            };
-            if entry.is_dir() {
-                let key_entry = build_key_entry(&entry, bucket, objects_ids, local_storage).await;
-                let key = Segment::new(file_name);
-                keys.insert(key, key_entry);
-            } else {
-                objects.push(entry);
+        scan.progress.directory();
+        let fingerprint = Fingerprint::of(path);
+        let mut cached = cached.unwrap_or_default();
+
+        let (objects, children) = if fingerprint.is_some() && cached.fingerprint == fingerprint {
+            let key = Key::from_bucket(bucket.borrow(), path).unwrap();
+            scan.progress.reused(cached.objects.len());
+            let objects = restore_objects(cached.objects, path, bucket.borrow(), key, scan).await;
+
+            let children = cached
+                .keys
+                .into_iter()
+                .map(|(seg, child)| (path.join(seg.as_ref()), seg, Some(child)))
+                .filter(|(path, _, _)| path.is_dir())
+                .collect::<Vec<_>>();
+
+            (objects, children)
+        } else {
+            let mut files = Vec::new();
+            let mut children = Vec::new();
+            let mut read_dir = path.read_dir().unwrap().into_iter();
+
+            while let Some(entry) = read_dir.next().and_then(|x| x.ok().map(|x| x.path())) {
+                let Some((entry, file_name)) = file_name_normalize(entry).await else {
+                    continue;
+                };
+                if entry.is_dir() {
+                    let key = Segment::new(file_name);
+                    let cached = cached.keys.remove(&key);
+                    children.push((entry, key, cached));
+                } else {
+                    files.push((entry, file_name));
+                }
             }
-        }
 
-        let objs = sync_objects(
-            objects,
-            bucket.borrow(),
-            Key::from_bucket(bucket.borrow(), path).unwrap(),
-            local_storage,
-            objects_ids,
-        )
-        .await;
-
-        KeyEntry {
-            objects: (!objs.is_empty()).then_some(objs),
+            let objects = sync_objects(
+                files,
+                bucket.borrow(),
+                Key::from_bucket(bucket.borrow(), path).unwrap(),
+                scan,
+            )
+            .await;
+
+            (objects, children)
+        };
+
+        let keys = join_all(children.into_iter().map(|(path, seg, cached)| async move {
+            let entry = build_key_entry(&path, bucket, cached, scan).await;
+            (seg, entry)
+        }))
+        .await
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+        let mut entry = KeyEntry {
+            objects: (!objects.is_empty()).then(|| {
+                let mut entries = Objects::new(scan.indexes);
+                entries.extend(objects);
+                entries
+            }),
             keys: (!keys.is_empty()).then_some(keys),
+            fingerprint,
             ..Default::default()
-        }
+        };
+        entry.refresh_usage();
+        entry
     }
     .boxed()
 }
 
-pub async fn sync_object_with_database(ls: &LocalStorage, objects_ids: Vec<ObjectId>) {
-    let pool = ls.pool.default_database().unwrap();
+async fn restore_objects(
+    cached: Vec<Object>,
+    path: &Path,
+    bucket: Bucket<'_>,
+    key: Key<'_>,
+    scan: &Scan<'_>,
+) -> Vec<Object> {
+    let cached = cached
+        .into_iter()
+        .map(|object| (path.join(&object.file_name), object))
+        .filter(|(path, _)| path.is_file())
+        .collect::<Vec<_>>();
+    if cached.is_empty() {
+        return Vec::new();
+    }
 
-    let objects = pool
-        .collection::<AsObjectDeserialize>(COLLECTION)
-        .find(doc! {"_id":{"$nin": objects_ids}})
-        .await
-        .unwrap()
-        .try_collect::<Vec<_>>()
-        .await
-        .expect("[ fn sync_object_with_database ] Failed to get Objects");
+    let names = cached
+        .iter()
+        .map(|(_, object)| {
+            scan.seen(&bucket, &key, &object.file_name);
+            object.file_name.clone()
+        })
+        .collect::<Vec<_>>();
+    let mut stored = {
+        let _permit = scan.db.acquire().await.unwrap();
+        match scan
+            .ls
+            .get_objects(bucket.borrow(), key.borrow(), &names)
+            .await
+        {
+            Ok(objects) => objects
+                .into_iter()
+                .map(|x| (x.file_name.clone(), x))
+                .collect::<HashMap<_, _>>(),
+            Err(er) => {
+                tracing::error!("[ fn restore_objects ] Failed to get Objects: {er}");
+                HashMap::new()
+            }
+        }
+    };
+
+    let objects = join_all(cached.into_iter().map(|(path, mut object)| {
+        let stored = stored.remove(&object.file_name);
+        let (bucket, key) = (bucket.borrow(), key.borrow());
+        async move {
+            if object.unchanged(&path) {
+                return match stored {
+                    Some(stored) => {
+                        object.preserve(stored);
+                        (object, false)
+                    }
+                    None => (object, true),
+                };
+            }
 
-    tracing::warn!(
-        "[ fn sync_object_with_database ] {} Object dont found in filesystem: {:#?}",
-        objects.len(),
-        objects
-    );
+            tracing::info!("[ fn restore_objects ] {{ Object changed on disk }} path: {path:?}");
+            let mut changed = scan.hash(&path).await;
+            if stored.is_none() {
+                changed.preserve(object);
+                return (changed, true);
+            }
+            let _permit = scan.db.acquire().await.unwrap();
+            if let Err(er) = scan.ls.sync_object(bucket, key, &mut changed).await {
+                tracing::error!("[ fn restore_objects ] {er}");
+            }
+            (changed, false)
+        }
+    }))
+    .await;
 
-    let objects = objects
-        .into_iter()
-        .filter_map(|x| x._id)
+    let missing = objects
+        .iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(object, _)| object.clone())
         .collect::<Vec<_>>();
 
-    let delete_result = pool
-        .collection::<Document>(COLLECTION)
-        .delete_many(doc! {"_id": {"$in": objects}})
-        .await
-        .expect("[ fn sync_object_with_database ] Failed to delete Objects");
-    tracing::warn!(
-        "[ fn sync_object_with_database ] {} Objects deleted",
-        delete_result.deleted_count
-    );
+    if !missing.is_empty() {
+        tracing::info!(
+            "[ fn restore_objects ] {} Objects restored from snapshot",
+            missing.len()
+        );
+        let _permit = scan.db.acquire().await.unwrap();
+        if let Err(er) = scan.ls.new_objects(bucket, key, &missing).await {
+            tracing::error!("[ fn restore_objects ] {er}");
+        }
+    }
+
+    objects.into_iter().map(|(object, _)| object).collect()
+}
+
+pub async fn sync_object_with_database(ls: &dyn MetadataStore, seen: Vec<ObjectLocation>) {
+    match ls.retain_objects(&seen).await {
+        Ok(deleted) => tracing::warn!("[ fn sync_object_with_database ] {deleted} Objects deleted"),
+        Err(er) => tracing::error!("[ fn sync_object_with_database ] Failed to sync Objects: {er}"),
+    }
+}
+
+impl KeyEntry {
+    pub fn get<'a>(&'a self, key: &'a Key<'_>) -> Option<&'a KeyEntry> {
+        if key.is_root() {
+            Some(self)
+        } else {
+            key.into_iter()
+                .try_fold(self, |entry, x| entry.keys.as_ref()?.get(&x))
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &Key<'_>) -> Option<&mut KeyEntry> {
+        if key.is_root() {
+            Some(self)
+        } else {
+            let mut entry = self;
+            let keys = key.into_iter().map(|x| x.owned()).collect::<Vec<_>>();
+
+            for key in keys {
+                entry = entry.keys.as_mut().and_then(|x| x.get_mut(&key))?;
+            }
+
+            Some(entry)
+        }
+    }
+
+    fn take(&mut self, key: &Key<'_>) -> Option<KeyEntry> {
+        let (parent, seg) = split_key(key);
+        let entry = self.get_mut(&parent)?;
+        let keys = entry.keys.as_mut()?;
+        let taken = keys.remove(&seg);
+        if keys.is_empty() {
+            entry.keys = None;
+        }
+        self.refresh(&parent);
+        taken
+    }
+
+    fn put(&mut self, key: &Key<'_>, entry: KeyEntry) -> Result<(), Box<KeyEntry>> {
+        let (parent, seg) = split_key(key);
+        let Some(parent) = self.get_mut(&parent) else {
+            return Err(Box::new(entry));
+        };
+
+        let keys = parent.keys.get_or_insert_default();
+        if keys.contains_key(&seg) {
+            return Err(Box::new(entry));
+        }
+        keys.insert(seg, entry);
+        self.refresh(key);
+        Ok(())
+    }
+
+    fn refresh_usage(&mut self) {
+        let mut usage = self.objects.as_ref().map(Usage::from).unwrap_or_default();
+        for child in self.keys.iter().flat_map(|x| x.values()) {
+            usage.add(&child.usage);
+        }
+        self.usage = usage;
+    }
+
+    fn refresh(&mut self, key: &Key<'_>) {
+        let path = match key.is_root() {
+            true => Vec::new(),
+            false => key.into_iter().map(|x| x.owned()).collect::<Vec<_>>(),
+        };
+        self.refresh_path(&path);
+    }
+
+    fn refresh_path(&mut self, path: &[Segment<'static>]) {
+        if let Some((seg, rest)) = path.split_first()
+            && let Some(child) = self.keys.as_mut().and_then(|x| x.get_mut(seg))
+        {
+            child.refresh_path(rest);
+        }
+        self.refresh_usage();
+    }
+
+    fn change(&mut self, change: Change, indexes: ObjectIndexes) -> bool {
+        let touched = change.location().1.map(Cowed::cloned);
+        let applied = match change {
+            Change::NewObject {
+                bucket: _,
+                key,
+                object,
+            } => {
+                let Some(entry) = self.get_mut(&key) else {
+                    return false;
+                };
+
+                entry.settle(&object.file_name);
+                entry.objects_mut(indexes).insert(object);
+                true
+            }
+            Change::PendingObject {
+                bucket,
+                key,
+                file_name,
+            } => {
+                let Some(entry) = self.get_mut(&key) else {
+                    tracing::error!("[ BucketMap ] The bucket {bucket} with key {key} not found");
+                    return false;
+                };
+
+                tracing::debug!("[ BucketMap ] Object {file_name} in progress in {bucket}/{key}");
+                entry.pending.get_or_insert_default().insert(file_name);
+                true
+            }
+            Change::UpdateObject {
+                bucket,
+                key,
+                object,
+            } => {
+                let Some(entry) = self.get_mut(&key) else {
+                    tracing::error!("[ BucketMap ] The bucket {bucket} with key {key} not found");
+                    return false;
+                };
+
+                entry.settle(&object.file_name);
+                entry.objects_mut(indexes).insert(object);
+                true
+            }
+            Change::NewKey { bucket, key } => {
+                let (parent, new_key) = split_key(&key);
+                if let Some(entry) = self.get_mut(&parent) {
+                    entry
+                        .keys
+                        .get_or_insert_default()
+                        .entry(new_key)
+                        .or_default();
+                    true
+                } else {
+                    tracing::debug!(
+                        "[ BucketMap ] New key, parent key {} not found in bucket {}",
+                        parent,
+                        bucket
+                    );
+                    false
+                }
+            }
+            Change::NameObject {
+                bucket,
+                key,
+                from,
+                to,
+            } => {
+                if let Some(entry) = self.get_mut(&key) {
+                    if entry.settle(&from) {
+                        tracing::debug!(
+                            "[ BucketMap ] Rename object in progress, from {from} to {to}, in {bucket}/{key}"
+                        );
+                        entry.pending.get_or_insert_default().insert(to);
+                        true
+                    } else if entry
+                        .objects
+                        .as_mut()
+                        .is_some_and(|x| x.rename(&from, to.clone()))
+                    {
+                        tracing::debug!(
+                            "[ BucketMap ] Rename object, from {} to {}, in {}/{}",
+                            from,
+                            to,
+                            bucket,
+                            key
+                        );
+                        true
+                    } else {
+                        tracing::debug!(
+                            "[ BucketMap ] Rename object, object {} not found, in {}/{}",
+                            from,
+                            bucket,
+                            key
+                        );
+                        false
+                    }
+                } else {
+                    false
+                }
+            }
+            Change::NameKey { bucket, from, to } => {
+                let (parent, from_seg) = split_key(&from);
+                let Some(entry) = self.get_mut(&parent) else {
+                    tracing::error!("[ BucketMap ] Key {from} not found in bucket {bucket}");
+                    return false;
+                };
+
+                let keys = entry.keys.get_or_insert_default();
+                if keys.contains_key(&to) {
+                    tracing::error!(
+                        "[ BucketMap ] Key {parent}/{to} already exists, i cannot rename the key {from}"
+                    );
+                    return false;
+                }
+
+                let old = keys.remove(&from_seg);
+                keys.insert(to, old.unwrap_or_default());
+                true
+            }
+            Change::DeleteObject {
+                bucket,
+                key,
+                file_name,
+            } => {
+                if let Some(entry) = self.get_mut(&key) {
+                    if entry.settle(&file_name) {
+                        tracing::debug!(
+                            "[ BucketMap ] object in progress {file_name} deleted from key {key}"
+                        );
+                        return true;
+                    }
+
+                    let Some(objs) = entry.objects.as_mut() else {
+                        tracing::error!("[ BucketMap ] Delete Object: I haven't objects in {key}");
+                        return false;
+                    };
+
+                    if objs.remove(&file_name).is_some() {
+                        tracing::debug!("[ BucketMap ] object {file_name} deleted from key {key}");
+                        true
+                    } else {
+                        tracing::error!("[ BucketMap ] object {file_name} not found in key {key}");
+                        false
+                    }
+                } else {
+                    tracing::error!("[ BucketMap ] The bucket {bucket} with key {key} not found");
+                    false
+                }
+            }
+            Change::DeleteKey { bucket, key } => {
+                let (parent, to_delete) = split_key(&key);
+                let Some(entry) = self.get_mut(&parent) else {
+                    return false;
+                };
+
+                if let Some(entry) = entry.keys.as_mut().and_then(|x| x.remove(&to_delete)) {
+                    tracing::info!(
+                        "[ BucketMap ] from bucket {bucket} delete: {:#?}",
+                        Fhs::create_branch(Some((&bucket).into()), &entry)
+                    );
+                    true
+                } else {
+                    tracing::error!("[ BucketMap ] Key {key} not found in bucket {bucket}");
+                    false
+                }
+            }
+            change => {
+                tracing::error!("[ BucketMap ] {change:?} spans more than one bucket");
+                false
+            }
+        };
+
+        if let Some(key) = touched {
+            self.refresh(&key);
+        }
+        applied
+    }
+
+    fn objects_mut(&mut self, indexes: ObjectIndexes) -> &mut Objects {
+        self.objects.get_or_insert_with(|| Objects::new(indexes))
+    }
+
+    fn settle(&mut self, file_name: &str) -> bool {
+        let Some(pending) = self.pending.as_mut() else {
+            return false;
+        };
+        let removed = pending.remove(file_name);
+        if pending.is_empty() {
+            self.pending = None;
+        }
+        removed
+    }
+
+    fn collect_brokers(&self, brokers: &mut Vec<<WSBroker as Actor>::ActorRef>) {
+        brokers.push(self.broker.clone());
+        for entry in self.keys.iter().flat_map(|x| x.values()) {
+            entry.collect_brokers(brokers);
+        }
+    }
 }
 
 impl std::fmt::Debug for KeyEntry {
@@ -519,6 +1060,8 @@ impl std::fmt::Debug for KeyEntry {
         f.debug_struct("KeyEntry")
             .field("objects", &self.objects)
             .field("keys", &self.keys)
+            .field("pending", &self.pending)
+            .field("usage", &self.usage)
             .field("observers", &"...")
             .finish()
     }
@@ -530,6 +1073,9 @@ impl std::default::Default for KeyEntry {
         Self {
             objects: None,
             keys: None,
+            pending: None,
+            fingerprint: None,
+            usage: Usage::default(),
             broker,
         }
     }
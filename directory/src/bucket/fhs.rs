@@ -1,10 +1,13 @@
 use serde::Serialize;
+use std::collections::BTreeSet;
 
 use crate::bucket::{
     Bucket, Cowed,
-    bucket_map::{BucketMap, KeyEntry},
+    bucket_map::KeyEntry,
     key::Segment,
-    object::Object,
+    listing::{Listed, Listing},
+    objects::Objects,
+    usage::Usage,
 };
 
 #[derive(Serialize, Debug)]
@@ -14,7 +17,13 @@ pub enum Fhs<'a> {
         #[serde(skip_serializing_if = "Option::is_none")]
         key: Option<Segment<'a>>,
         inner_key: Option<Vec<Fhs<'a>>>,
-        objects: Option<&'a Vec<Object>>,
+        objects: Option<Vec<Listed<'a>>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pending: Option<&'a BTreeSet<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        usage: Option<&'a Usage>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
     },
     Leaf(Segment<'a>),
 }
@@ -28,7 +37,46 @@ impl<'a> Fhs<'a> {
                     .map(|(k, v)| Fhs::create_branch(Some(k.borrow()), v))
                     .collect::<Vec<Fhs<'_>>>()
             }),
-            objects: entry.objects.as_ref(),
+            objects: entry.objects.as_ref().map(listed_all),
+            pending: entry.pending.as_ref(),
+            usage: Some(&entry.usage),
+            next_cursor: None,
+        }
+    }
+
+    pub fn listed(
+        key: Option<Segment<'a>>,
+        entry: &'a KeyEntry,
+        listing: &'a Listing,
+        depth: usize,
+        paginate: bool,
+    ) -> Self {
+        let (objects, next_cursor) = match entry.objects.as_ref() {
+            Some(objects) => {
+                let (page, next) = listing.page(objects, paginate);
+                let page = page
+                    .into_iter()
+                    .map(|x| Listed::new(x, listing.fields.as_ref()))
+                    .collect();
+                (Some(page), next)
+            }
+            None => (None, None),
+        };
+
+        Self::Branch {
+            key,
+            inner_key: entry.keys.as_ref().filter(|_| depth > 0).map(|x| {
+                x.iter()
+                    .map(|(k, v)| match depth {
+                        1 => Self::Leaf(k.borrow()),
+                        _ => Self::listed(Some(k.borrow()), v, listing, depth - 1, false),
+                    })
+                    .collect::<Vec<_>>()
+            }),
+            objects,
+            pending: entry.pending.as_ref(),
+            usage: Some(&entry.usage),
+            next_cursor,
         }
     }
 }
@@ -41,7 +89,10 @@ impl<'a> From<&'a KeyEntry> for Fhs<'a> {
                 .keys
                 .as_ref()
                 .map(|x| x.keys().map(|x| Self::Leaf(x.borrow())).collect::<Vec<_>>()),
-            objects: value.objects.as_ref(),
+            objects: value.objects.as_ref().map(listed_all),
+            pending: value.pending.as_ref(),
+            usage: Some(&value.usage),
+            next_cursor: None,
         }
     }
 }
@@ -57,22 +108,13 @@ impl<'a> From<Vec<&'a Bucket<'_>>> for Fhs<'a> {
                     .collect::<Vec<_>>(),
             ),
             objects: None,
+            pending: None,
+            usage: None,
+            next_cursor: None,
         }
     }
 }
 
-impl<'a> From<&'a BucketMap> for Fhs<'a> {
-    fn from(value: &'a BucketMap) -> Self {
-        Self::Branch {
-            key: Some(Segment::new("/")),
-            inner_key: Some(
-                value
-                    .tree
-                    .iter()
-                    .map(|(k, v)| Fhs::create_branch(Some(k.into()), v))
-                    .collect::<Vec<_>>(),
-            ),
-            objects: None,
-        }
-    }
+fn listed_all(objects: &Objects) -> Vec<Listed<'_>> {
+    objects.iter().map(|x| Listed::new(x, None)).collect()
 }
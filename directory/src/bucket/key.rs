@@ -49,6 +49,36 @@ impl<'a> Key<'a> {
             .map(|(_, x)| x.strip_prefix("/").unwrap_or(x).to_string())
             .map(|x| Self::new(if x.is_empty() { ".".to_string() } else { x }))
     }
+
+    pub fn child(&self, name: &str) -> Key<'static> {
+        if self.is_root() {
+            Key::new(name.to_string())
+        } else {
+            Key::new(format!("{}/{name}", self.name()))
+        }
+    }
+
+    pub fn in_subtree(&self, root: &Key<'_>) -> bool {
+        root.is_root()
+            || self == root
+            || self
+                .name()
+                .strip_prefix(root.name())
+                .is_some_and(|x| x.starts_with('/'))
+    }
+
+    pub fn rebase(&self, from: &Key<'_>, to: &Key<'_>) -> Key<'static> {
+        if self == from {
+            return to.cloned();
+        }
+
+        let rest = if from.is_root() {
+            self.name()
+        } else {
+            &self.name()[from.name().len() + 1..]
+        };
+        to.child(rest)
+    }
 }
 
 impl<'a> std::clone::Clone for Key<'a> {
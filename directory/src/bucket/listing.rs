@@ -0,0 +1,275 @@
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize, Serializer, ser::Error};
+use time::{OffsetDateTime, serde::rfc3339::option};
+use url::form_urlencoded;
+
+use crate::bucket::{object::Object, objects::Objects};
+
+const FIELDS: [&str; 10] = [
+    "size",
+    "owner",
+    "file_name",
+    "checksum",
+    "seen_by",
+    "taken_by",
+    "modified",
+    "accessed",
+    "created",
+    "fingerprint",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: SortField,
+    descending: bool,
+    size: i64,
+    #[serde(with = "option")]
+    modified: Option<OffsetDateTime>,
+    file_name: String,
+}
+
+impl Cursor {
+    fn new(sort: SortField, descending: bool, object: &Object) -> Self {
+        Self {
+            sort,
+            descending,
+            size: object.size,
+            modified: object.modified.get(),
+            file_name: object.file_name.clone(),
+        }
+    }
+
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(value).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+#[derive(Debug)]
+pub struct Listing {
+    pub depth: usize,
+    pub limit: Option<usize>,
+    cursor: Option<Cursor>,
+    pub sort: SortField,
+    pub descending: bool,
+    pub fields: Option<BTreeSet<String>>,
+    pub checksum: Option<String>,
+    pub modified_after: Option<OffsetDateTime>,
+    pub modified_before: Option<OffsetDateTime>,
+}
+
+impl Default for Listing {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            limit: None,
+            cursor: None,
+            sort: SortField::Name,
+            descending: false,
+            fields: None,
+            checksum: None,
+            modified_after: None,
+            modified_before: None,
+        }
+    }
+}
+
+impl Listing {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut listing = Self::default();
+
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            match name {
+                "depth" => {
+                    listing.depth = value
+                        .parse()
+                        .map_err(|_| format!("Invalid depth {value:?}"))?;
+                }
+                "limit" => {
+                    listing.limit = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|x| *x > 0)
+                            .ok_or_else(|| format!("Invalid limit {value:?}"))?,
+                    );
+                }
+                "cursor" => {
+                    listing.cursor =
+                        Some(Cursor::decode(value).ok_or_else(|| "Invalid cursor".to_string())?);
+                }
+                "sort" => {
+                    listing.sort = match value {
+                        "name" => SortField::Name,
+                        "size" => SortField::Size,
+                        "modified" => SortField::Modified,
+                        _ => return Err(format!("Invalid sort {value:?}")),
+                    };
+                }
+                "order" => {
+                    listing.descending = match value {
+                        "asc" => false,
+                        "desc" => true,
+                        _ => return Err(format!("Invalid order {value:?}")),
+                    };
+                }
+                "fields" => {
+                    let fields = value
+                        .split(',')
+                        .filter(|x| !x.is_empty())
+                        .map(ToString::to_string)
+                        .collect::<BTreeSet<_>>();
+                    if let Some(field) = fields.iter().find(|x| !FIELDS.contains(&x.as_str())) {
+                        return Err(format!("Invalid field {field:?}"));
+                    }
+                    listing.fields = Some(fields);
+                }
+                "checksum" => listing.checksum = Some(value.to_string()),
+                "modified_after" => listing.modified_after = Some(timestamp(name, value)?),
+                "modified_before" => listing.modified_before = Some(timestamp(name, value)?),
+                _ => {}
+            }
+        }
+
+        if listing
+            .cursor
+            .as_ref()
+            .is_some_and(|x| x.sort != listing.sort || x.descending != listing.descending)
+        {
+            return Err("The cursor belongs to another sort order".to_string());
+        }
+
+        Ok(listing)
+    }
+
+    pub fn page<'a>(
+        &self,
+        objects: &'a Objects,
+        paginate: bool,
+    ) -> (Vec<&'a Object>, Option<String>) {
+        let cursor = self.cursor.as_ref().filter(|_| paginate);
+        let limit = self.limit.filter(|_| paginate);
+        let filtered = self.checksum.is_some()
+            || self.modified_after.is_some()
+            || self.modified_before.is_some();
+
+        let mut page = match (self.sort, filtered) {
+            (SortField::Name, false) => {
+                let names =
+                    objects.names_after(cursor.map(|x| x.file_name.as_str()), self.descending);
+                match limit {
+                    Some(limit) => names.take(limit + 1).collect(),
+                    None => names.collect(),
+                }
+            }
+            _ => {
+                let mut candidates = match filtered {
+                    true => objects.query(
+                        self.checksum.as_deref(),
+                        self.modified_after,
+                        self.modified_before,
+                    ),
+                    false => objects.iter().collect(),
+                };
+                if let Some(cursor) = cursor {
+                    candidates.retain(|x| self.compare_cursor(x, cursor) == Ordering::Greater);
+                }
+                candidates.sort_by(|a, b| self.compare(a, b));
+                candidates
+            }
+        };
+
+        let next = match limit {
+            Some(limit) if page.len() > limit => {
+                page.truncate(limit);
+                page.last()
+                    .map(|x| Cursor::new(self.sort, self.descending, x).encode())
+            }
+            _ => None,
+        };
+
+        (page, next)
+    }
+
+    fn compare(&self, a: &Object, b: &Object) -> Ordering {
+        let ordering = match self.sort {
+            SortField::Name => a.file_name.cmp(&b.file_name),
+            SortField::Size => (a.size, &a.file_name).cmp(&(b.size, &b.file_name)),
+            SortField::Modified => {
+                (a.modified.get(), &a.file_name).cmp(&(b.modified.get(), &b.file_name))
+            }
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    fn compare_cursor(&self, object: &Object, cursor: &Cursor) -> Ordering {
+        let ordering = match self.sort {
+            SortField::Name => object.file_name.cmp(&cursor.file_name),
+            SortField::Size => {
+                (object.size, &object.file_name).cmp(&(cursor.size, &cursor.file_name))
+            }
+            SortField::Modified => (object.modified.get(), &object.file_name)
+                .cmp(&(cursor.modified, &cursor.file_name)),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+fn timestamp(name: &str, value: &str) -> Result<OffsetDateTime, String> {
+    value
+        .parse()
+        .ok()
+        .and_then(|x| OffsetDateTime::from_unix_timestamp(x).ok())
+        .ok_or_else(|| format!("Invalid {name} {value:?}"))
+}
+
+#[derive(Debug)]
+pub struct Listed<'a> {
+    object: &'a Object,
+    fields: Option<&'a BTreeSet<String>>,
+}
+
+impl<'a> Listed<'a> {
+    pub fn new(object: &'a Object, fields: Option<&'a BTreeSet<String>>) -> Self {
+        Self { object, fields }
+    }
+}
+
+impl Serialize for Listed<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(fields) = self.fields else {
+            return self.object.serialize(serializer);
+        };
+
+        match serde_json::to_value(self.object).map_err(S::Error::custom)? {
+            serde_json::Value::Object(mut map) => {
+                map.retain(|k, _| fields.contains(k));
+                map.serialize(serializer)
+            }
+            value => value.serialize(serializer),
+        }
+    }
+}
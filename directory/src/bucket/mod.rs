@@ -2,7 +2,13 @@ pub mod bucket_map;
 pub mod error;
 pub mod fhs;
 pub mod key;
+pub mod listing;
 pub mod object;
+pub mod objects;
+pub mod progress;
+pub mod search;
+pub mod snapshot;
+pub mod usage;
 pub mod utils;
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
@@ -86,6 +86,33 @@ pub struct Object {
     pub modified: ObjectModified,
     pub accessed: ObjectAccessed,
     pub created: ObjectCreated,
+    #[serde(default)]
+    pub fingerprint: Option<ObjectFingerprint>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectFingerprint {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ino: u64,
+}
+
+impl From<&Metadata> for ObjectFingerprint {
+    fn from(value: &Metadata) -> Self {
+        Self {
+            size: value.size(),
+            mtime: value.mtime(),
+            mtime_nsec: value.mtime_nsec(),
+            ino: value.ino(),
+        }
+    }
+}
+
+impl ObjectFingerprint {
+    pub fn of(path: &Path) -> Option<Self> {
+        path.metadata().ok().as_ref().map(Self::from)
+    }
 }
 
 impl Object {
@@ -94,9 +121,7 @@ impl Object {
         T: AsRef<Path>,
     {
         let path = path.as_ref();
-        let meta = path.metadata().ok();
-        let (modified, accessed, created, size) = get_info_metadata(meta);
-
+        let before = ObjectFingerprint::of(path);
         let checksum = match CheckSum::new(path.to_path_buf()).check_sum_async().await {
             Ok(msg) => msg,
             Err(er) => {
@@ -107,6 +132,25 @@ impl Object {
             }
         };
 
+        Self::with_checksum(path, owner, checksum, before)
+    }
+
+    pub fn with_checksum(
+        path: &Path,
+        owner: OwnerFile,
+        checksum: String,
+        before: Option<ObjectFingerprint>,
+    ) -> Self {
+        let meta = path.metadata().ok();
+        let fingerprint = meta
+            .as_ref()
+            .map(ObjectFingerprint::from)
+            .filter(|x| Some(*x) == before);
+        if meta.is_some() && fingerprint.is_none() {
+            tracing::debug!("[ Object ] {path:?} changed while hashing, no fingerprint stored");
+        }
+        let (modified, accessed, created, size) = get_info_metadata(meta);
+
         let file_name = path
             .file_name()
             .and_then(|x| x.to_str())
@@ -121,18 +165,45 @@ impl Object {
             owner,
             accessed,
             created,
+            fingerprint,
             ..Default::default()
         }
     }
 }
 
+impl ObjectModified {
+    pub fn get(&self) -> Option<OffsetDateTime> {
+        self.0
+    }
+}
+
+impl ObjectCreated {
+    pub fn get(&self) -> Option<OffsetDateTime> {
+        self.0
+    }
+}
+
+impl Object {
+    pub fn unchanged(&self, path: &Path) -> bool {
+        self.fingerprint
+            .is_some_and(|x| Some(x) == ObjectFingerprint::of(path))
+    }
+
+    pub fn preserve(&mut self, stored: Object) {
+        self.owner = stored.owner;
+        self.seen_by = stored.seen_by;
+        self.taken_by = stored.taken_by;
+        self.created = stored.created;
+    }
+}
+
 impl std::cmp::PartialEq for Object {
     fn eq(&self, other: &Self) -> bool {
         self.file_name == other.file_name && self.checksum == other.checksum
     }
 }
 
-#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type", content = "value")]
 pub enum OwnerFile {
     #[default]
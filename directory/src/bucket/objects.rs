@@ -0,0 +1,220 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound,
+};
+
+use time::OffsetDateTime;
+
+use crate::bucket::object::Object;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ObjectIndexes {
+    pub checksum: bool,
+    pub modified: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Objects {
+    by_name: BTreeMap<String, Object>,
+    by_checksum: Option<HashMap<String, BTreeSet<String>>>,
+    by_modified: Option<BTreeSet<(OffsetDateTime, String)>>,
+    total_size: i64,
+    last_modified: Option<OffsetDateTime>,
+}
+
+impl Objects {
+    pub fn new(indexes: ObjectIndexes) -> Self {
+        Self {
+            by_name: BTreeMap::new(),
+            by_checksum: indexes.checksum.then(HashMap::new),
+            by_modified: indexes.modified.then(BTreeSet::new),
+            total_size: 0,
+            last_modified: None,
+        }
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<&Object> {
+        self.by_name.get(file_name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn total_size(&self) -> i64 {
+        self.total_size
+    }
+
+    pub fn last_modified(&self) -> Option<OffsetDateTime> {
+        self.last_modified
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Object> {
+        self.by_name.values()
+    }
+
+    pub fn names_after(
+        &self,
+        file_name: Option<&str>,
+        descending: bool,
+    ) -> Box<dyn Iterator<Item = &Object> + '_> {
+        let range = match (file_name, descending) {
+            (None, _) => (Bound::Unbounded, Bound::Unbounded),
+            (Some(x), false) => (Bound::Excluded(x), Bound::Unbounded),
+            (Some(x), true) => (Bound::Unbounded, Bound::Excluded(x)),
+        };
+
+        let objects = self.by_name.range::<str, _>(range).map(|(_, x)| x);
+        match descending {
+            true => Box::new(objects.rev()),
+            false => Box::new(objects),
+        }
+    }
+
+    pub fn insert(&mut self, object: Object) -> Option<Object> {
+        let old = self.remove(&object.file_name);
+        self.index(&object);
+        self.by_name.insert(object.file_name.clone(), object);
+        old
+    }
+
+    pub fn remove(&mut self, file_name: &str) -> Option<Object> {
+        let object = self.by_name.remove(file_name)?;
+        self.unindex(&object);
+        Some(object)
+    }
+
+    pub fn rename(&mut self, from: &str, to: String) -> bool {
+        let Some(mut object) = self.remove(from) else {
+            return false;
+        };
+        object.file_name = to;
+        self.insert(object);
+        true
+    }
+
+    pub fn with_checksum(&self, checksum: &str) -> Vec<&Object> {
+        match self.by_checksum.as_ref() {
+            Some(index) => index
+                .get(checksum)
+                .into_iter()
+                .flatten()
+                .filter_map(|x| self.by_name.get(x))
+                .collect(),
+            None => self.iter().filter(|x| x.checksum == checksum).collect(),
+        }
+    }
+
+    pub fn query(
+        &self,
+        checksum: Option<&str>,
+        after: Option<OffsetDateTime>,
+        before: Option<OffsetDateTime>,
+    ) -> Vec<&Object> {
+        match checksum {
+            Some(checksum) if after.is_none() && before.is_none() => self.with_checksum(checksum),
+            Some(checksum) => self
+                .with_checksum(checksum)
+                .into_iter()
+                .filter(|x| modified_in(x, after, before))
+                .collect(),
+            None => self.modified_between(after, before),
+        }
+    }
+
+    pub fn modified_between(
+        &self,
+        after: Option<OffsetDateTime>,
+        before: Option<OffsetDateTime>,
+    ) -> Vec<&Object> {
+        match self.by_modified.as_ref() {
+            Some(index) => {
+                let from = after.map_or(Bound::Unbounded, |x| Bound::Included((x, String::new())));
+                index
+                    .range((from, Bound::Unbounded))
+                    .take_while(|(time, _)| before.is_none_or(|x| *time < x))
+                    .filter_map(|(_, name)| self.by_name.get(name))
+                    .collect()
+            }
+            None => {
+                let mut objects = self
+                    .iter()
+                    .filter(|x| modified_in(x, after, before))
+                    .collect::<Vec<_>>();
+                objects.sort_by_key(|x| x.modified.get());
+                objects
+            }
+        }
+    }
+
+    fn index(&mut self, object: &Object) {
+        self.total_size += object.size;
+        self.last_modified = self.last_modified.max(object.modified.get());
+
+        if let Some(index) = self.by_checksum.as_mut() {
+            index
+                .entry(object.checksum.clone())
+                .or_default()
+                .insert(object.file_name.clone());
+        }
+
+        if let (Some(index), Some(modified)) = (self.by_modified.as_mut(), object.modified.get()) {
+            index.insert((modified, object.file_name.clone()));
+        }
+    }
+
+    fn unindex(&mut self, object: &Object) {
+        if let Some(index) = self.by_checksum.as_mut()
+            && let Some(names) = index.get_mut(&object.checksum)
+        {
+            names.remove(&object.file_name);
+            if names.is_empty() {
+                index.remove(&object.checksum);
+            }
+        }
+
+        if let (Some(index), Some(modified)) = (self.by_modified.as_mut(), object.modified.get()) {
+            index.remove(&(modified, object.file_name.clone()));
+        }
+
+        self.total_size -= object.size;
+        if object.modified.get().is_some() && object.modified.get() == self.last_modified {
+            self.last_modified = match self.by_modified.as_ref() {
+                Some(index) => index.last().map(|(time, _)| *time),
+                None => self.by_name.values().filter_map(|x| x.modified.get()).max(),
+            };
+        }
+    }
+}
+
+fn modified_in(
+    object: &Object,
+    after: Option<OffsetDateTime>,
+    before: Option<OffsetDateTime>,
+) -> bool {
+    object
+        .modified
+        .get()
+        .is_some_and(|time| after.is_none_or(|x| time >= x) && before.is_none_or(|x| time < x))
+}
+
+impl Extend<Object> for Objects {
+    fn extend<T: IntoIterator<Item = Object>>(&mut self, iter: T) {
+        for object in iter {
+            self.insert(object);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Objects {
+    type Item = &'a Object;
+    type IntoIter = std::collections::btree_map::Values<'a, String, Object>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.by_name.values()
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+#[derive(Debug)]
+pub struct BuildProgress {
+    started: Instant,
+    directories: AtomicUsize,
+    objects: AtomicUsize,
+    hashed: AtomicUsize,
+    reused: AtomicUsize,
+    ready: watch::Sender<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Progress {
+    pub ready: bool,
+    pub directories: usize,
+    pub objects: usize,
+    pub hashed: usize,
+    pub reused: usize,
+    pub elapsed_ms: u128,
+}
+
+impl Default for BuildProgress {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            directories: AtomicUsize::new(0),
+            objects: AtomicUsize::new(0),
+            hashed: AtomicUsize::new(0),
+            reused: AtomicUsize::new(0),
+            ready: watch::Sender::new(false),
+        }
+    }
+}
+
+impl BuildProgress {
+    pub fn directory(&self) {
+        self.directories.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn objects(&self, n: usize) {
+        self.objects.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn hashed(&self) {
+        self.hashed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reused(&self, n: usize) {
+        self.reused.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.send_replace(true);
+        tracing::info!("[ BuildProgress ] Ready: {:?}", self.report());
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.ready.borrow()
+    }
+
+    pub async fn wait_ready(&self) {
+        _ = self.ready.subscribe().wait_for(|x| *x).await;
+    }
+
+    pub fn report(&self) -> Progress {
+        Progress {
+            ready: self.is_ready(),
+            directories: self.directories.load(Ordering::Relaxed),
+            objects: self.objects.load(Ordering::Relaxed),
+            hashed: self.hashed.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            elapsed_ms: self.started.elapsed().as_millis(),
+        }
+    }
+
+    pub fn log_every(self: Arc<Self>, every: Duration) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => tracing::info!("[ BuildProgress ] {:?}", self.report()),
+                    _ = self.wait_ready() => break,
+                }
+            }
+        });
+    }
+}
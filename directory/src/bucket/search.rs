@@ -0,0 +1,525 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound,
+    path::Path,
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use regex::Regex;
+use serde::Serialize;
+use time::{OffsetDateTime, serde::rfc3339::option};
+use url::form_urlencoded;
+
+use crate::{
+    bucket::{
+        Bucket, Cowed,
+        bucket_map::KeyEntry,
+        key::Key,
+        object::{Object, OwnerFile},
+    },
+    manager::{Change, KeyLocation, ObjectLocation},
+};
+
+pub type Location = (Bucket<'static>, Key<'static>, String);
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+const MAX_SCAN: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct Indexed {
+    size: i64,
+    checksum: String,
+    owner: OwnerFile,
+    modified: Option<OffsetDateTime>,
+    created: Option<OffsetDateTime>,
+    extension: Option<String>,
+}
+
+impl From<&Object> for Indexed {
+    fn from(value: &Object) -> Self {
+        Self {
+            size: value.size,
+            checksum: value.checksum.clone(),
+            owner: value.owner.clone(),
+            modified: value.modified.get(),
+            created: value.created.get(),
+            extension: extension(&value.file_name),
+        }
+    }
+}
+
+fn extension(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(str::to_lowercase)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub bucket: Bucket<'static>,
+    pub key: Key<'static>,
+    pub file_name: String,
+    pub size: i64,
+    pub checksum: String,
+    pub owner: OwnerFile,
+    #[serde(with = "option")]
+    pub modified: Option<OffsetDateTime>,
+    #[serde(with = "option")]
+    pub created: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    pub hits: Vec<Hit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Duplicate {
+    pub checksum: String,
+    pub size: i64,
+    pub wasted: i64,
+    pub locations: Vec<ObjectLocation>,
+}
+
+#[derive(Debug)]
+pub struct SearchQuery {
+    pub name: Option<Regex>,
+    pub extension: Option<String>,
+    pub size_min: Option<i64>,
+    pub size_max: Option<i64>,
+    pub modified_after: Option<OffsetDateTime>,
+    pub modified_before: Option<OffsetDateTime>,
+    pub created_after: Option<OffsetDateTime>,
+    pub created_before: Option<OffsetDateTime>,
+    pub owner: Option<OwnerFile>,
+    pub checksum: Option<String>,
+    pub limit: usize,
+    pub cursor: Option<Location>,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self {
+            name: None,
+            extension: None,
+            size_min: None,
+            size_max: None,
+            modified_after: None,
+            modified_before: None,
+            created_after: None,
+            created_before: None,
+            owner: None,
+            checksum: None,
+            limit: DEFAULT_LIMIT,
+            cursor: None,
+        }
+    }
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut search = Self::default();
+
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            match name.as_ref() {
+                "name" => search.name = Some(Self::glob(&value)?),
+                "regex" => search.name = Some(Self::regex(&value)?),
+                "extension" => search.extension = Some(Self::extension(&value)),
+                "size_min" => search.size_min = Some(number(&name, &value)?),
+                "size_max" => search.size_max = Some(number(&name, &value)?),
+                "modified_after" => {
+                    search.modified_after = Some(Self::timestamp(number(&name, &value)?)?)
+                }
+                "modified_before" => {
+                    search.modified_before = Some(Self::timestamp(number(&name, &value)?)?)
+                }
+                "created_after" => {
+                    search.created_after = Some(Self::timestamp(number(&name, &value)?)?)
+                }
+                "created_before" => {
+                    search.created_before = Some(Self::timestamp(number(&name, &value)?)?)
+                }
+                "owner" => search.owner = Some(Self::owner(&value)),
+                "checksum" => search.checksum = Some(value.into_owned()),
+                "limit" => search.limit = Self::limit(number(&name, &value)?)?,
+                "cursor" => search.cursor = Some(Self::cursor(&value)?),
+                _ => {}
+            }
+        }
+
+        Ok(search)
+    }
+
+    pub fn glob(glob: &str) -> Result<Regex, String> {
+        let pattern = glob
+            .split('*')
+            .map(|x| {
+                x.split('?')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .collect::<Vec<_>>()
+            .join(".*");
+        Self::regex(&format!("^{pattern}$"))
+    }
+
+    pub fn regex(regex: &str) -> Result<Regex, String> {
+        Regex::new(regex).map_err(|er| format!("Invalid pattern {regex:?}: {er}"))
+    }
+
+    pub fn extension(extension: &str) -> String {
+        extension.trim_start_matches('.').to_lowercase()
+    }
+
+    pub fn owner(owner: &str) -> OwnerFile {
+        match owner {
+            "system" => OwnerFile::System,
+            user => OwnerFile::User(user.to_string()),
+        }
+    }
+
+    pub fn timestamp(seconds: i64) -> Result<OffsetDateTime, String> {
+        OffsetDateTime::from_unix_timestamp(seconds)
+            .map_err(|_| format!("Invalid timestamp {seconds}"))
+    }
+
+    pub fn limit(limit: i64) -> Result<usize, String> {
+        usize::try_from(limit)
+            .ok()
+            .filter(|x| (1..=MAX_LIMIT).contains(x))
+            .ok_or_else(|| format!("The limit must be between 1 and {MAX_LIMIT}"))
+    }
+
+    pub fn cursor(cursor: &str) -> Result<Location, String> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|x| serde_json::from_slice::<(String, String, String)>(&x).ok())
+            .map(|(bucket, key, file_name)| {
+                (Bucket::new_unchecked(bucket), Key::new(key), file_name)
+            })
+            .ok_or_else(|| "Invalid cursor".to_string())
+    }
+
+    fn matches(&self, (_, _, file_name): &Location, indexed: &Indexed) -> bool {
+        self.name.as_ref().is_none_or(|x| x.is_match(file_name))
+            && self
+                .extension
+                .as_ref()
+                .is_none_or(|x| indexed.extension.as_ref() == Some(x))
+            && self.size_min.is_none_or(|x| indexed.size >= x)
+            && self.size_max.is_none_or(|x| indexed.size <= x)
+            && in_range(indexed.modified, self.modified_after, self.modified_before)
+            && in_range(indexed.created, self.created_after, self.created_before)
+            && self.owner.as_ref().is_none_or(|x| *x == indexed.owner)
+            && self
+                .checksum
+                .as_ref()
+                .is_none_or(|x| *x == indexed.checksum)
+    }
+}
+
+fn in_range(
+    time: Option<OffsetDateTime>,
+    after: Option<OffsetDateTime>,
+    before: Option<OffsetDateTime>,
+) -> bool {
+    (after.is_none() && before.is_none())
+        || time
+            .is_some_and(|time| after.is_none_or(|x| time >= x) && before.is_none_or(|x| time < x))
+}
+
+fn number(name: &str, value: &str) -> Result<i64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid {name} {value:?}"))
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    entries: BTreeMap<Location, Indexed>,
+    by_extension: HashMap<String, BTreeSet<Location>>,
+    by_checksum: HashMap<String, BTreeSet<Location>>,
+}
+
+impl SearchIndex {
+    pub fn add_entry(&mut self, bucket: &Bucket<'_>, key: &Key<'_>, entry: &KeyEntry) {
+        for object in entry.objects.iter().flatten() {
+            self.insert(
+                (bucket.cloned(), key.cloned(), object.file_name.clone()),
+                object.into(),
+            );
+        }
+
+        for (seg, child) in entry.keys.iter().flatten() {
+            self.add_entry(bucket, &key.child(seg.as_ref()), child);
+        }
+    }
+
+    pub fn apply(&mut self, change: &Change) {
+        match change {
+            Change::NewObject {
+                bucket,
+                key,
+                object,
+            }
+            | Change::UpdateObject {
+                bucket,
+                key,
+                object,
+            } => {
+                let location = (bucket.cloned(), key.cloned(), object.file_name.clone());
+                self.remove(&location);
+                self.insert(location, object.into());
+            }
+            Change::NameObject {
+                bucket,
+                key,
+                from,
+                to,
+            } => {
+                let from = (bucket.cloned(), key.cloned(), from.clone());
+                let to = (bucket.cloned(), key.cloned(), to.clone());
+                self.relocate(vec![(from, to)]);
+            }
+            Change::DeleteObject {
+                bucket,
+                key,
+                file_name,
+            } => {
+                self.remove(&(bucket.cloned(), key.cloned(), file_name.clone()));
+            }
+            Change::MoveObject { from, to } => {
+                self.relocate(vec![(location(from), location(to))]);
+            }
+            Change::NameKey { bucket, from, to } => {
+                let from = KeyLocation {
+                    bucket: bucket.cloned(),
+                    key: from.cloned(),
+                };
+                let to = KeyLocation {
+                    bucket: bucket.cloned(),
+                    key: from.parent().child(to.as_ref()),
+                };
+                self.move_key(&from, &to);
+            }
+            Change::MoveKey { from, to } => self.move_key(from, to),
+            Change::NameBucket { from, to } => {
+                let moves = self
+                    .in_bucket(from)
+                    .map(|x| (x.clone(), (to.cloned(), x.1.cloned(), x.2.clone())))
+                    .collect();
+                self.relocate(moves);
+            }
+            Change::DeleteKey { bucket, key } => {
+                let removed = self
+                    .in_bucket(bucket)
+                    .filter(|(_, k, _)| k.in_subtree(key))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for location in removed {
+                    self.remove(&location);
+                }
+            }
+            Change::DeleteBucket { bucket } => {
+                let removed = self.in_bucket(bucket).cloned().collect::<Vec<_>>();
+                for location in removed {
+                    self.remove(&location);
+                }
+            }
+            Change::NewKey { .. }
+            | Change::NewBucket { .. }
+            | Change::PendingObject { .. }
+            | Change::Resync { .. } => {}
+        }
+    }
+
+    pub fn search(&self, query: &SearchQuery, allowed: impl Fn(&Bucket<'_>) -> bool) -> SearchPage {
+        let lower = query
+            .cursor
+            .as_ref()
+            .map_or(Bound::Unbounded, Bound::Excluded);
+        let secondary = match (&query.checksum, &query.extension) {
+            (Some(checksum), _) => Some(self.by_checksum.get(checksum)),
+            (None, Some(extension)) => Some(self.by_extension.get(extension)),
+            (None, None) => None,
+        };
+
+        let candidates: Box<dyn Iterator<Item = (&Location, &Indexed)> + '_> = match secondary {
+            Some(locations) => Box::new(
+                locations
+                    .into_iter()
+                    .flat_map(move |x| x.range::<Location, _>((lower, Bound::Unbounded)))
+                    .filter_map(|x| self.entries.get_key_value(x)),
+            ),
+            None => Box::new(self.entries.range::<Location, _>((lower, Bound::Unbounded))),
+        };
+
+        let mut hits = Vec::new();
+        let mut last = None;
+        let mut next = None;
+        for (scanned, (location, indexed)) in candidates.enumerate() {
+            if scanned == MAX_SCAN {
+                next = last;
+                break;
+            }
+            if allowed(&location.0) && query.matches(location, indexed) {
+                if hits.len() == query.limit {
+                    next = last;
+                    break;
+                }
+                let (bucket, key, file_name) = location;
+                hits.push(Hit {
+                    bucket: bucket.cloned(),
+                    key: key.cloned(),
+                    file_name: file_name.clone(),
+                    size: indexed.size,
+                    checksum: indexed.checksum.clone(),
+                    owner: indexed.owner.clone(),
+                    modified: indexed.modified,
+                    created: indexed.created,
+                });
+            }
+            last = Some(location);
+        }
+
+        let next_cursor = next.map(|(bucket, key, file_name): &Location| {
+            URL_SAFE_NO_PAD.encode(
+                serde_json::to_vec(&(bucket.name(), key.name(), file_name)).unwrap_or_default(),
+            )
+        });
+
+        SearchPage { hits, next_cursor }
+    }
+
+    pub fn locate(
+        &self,
+        checksum: &str,
+        allowed: impl Fn(&Bucket<'_>) -> bool,
+    ) -> Vec<ObjectLocation> {
+        self.by_checksum
+            .get(checksum)
+            .into_iter()
+            .flatten()
+            .filter(|(bucket, _, _)| allowed(bucket))
+            .map(object_location)
+            .collect()
+    }
+
+    pub fn duplicates(&self, allowed: impl Fn(&Bucket<'_>) -> bool) -> Vec<Duplicate> {
+        let mut duplicates = self
+            .by_checksum
+            .iter()
+            .filter(|(checksum, locations)| !checksum.is_empty() && locations.len() > 1)
+            .filter_map(|(checksum, locations)| {
+                let locations = locations
+                    .iter()
+                    .filter(|(bucket, _, _)| allowed(bucket))
+                    .collect::<Vec<_>>();
+                let size = self.entries.get(*locations.first()?)?.size;
+                let copies = i64::try_from(locations.len()).ok().filter(|x| *x > 1)?;
+
+                Some(Duplicate {
+                    checksum: checksum.clone(),
+                    size,
+                    wasted: size * (copies - 1),
+                    locations: locations.into_iter().map(object_location).collect(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        duplicates.sort_by(|a, b| {
+            b.wasted
+                .cmp(&a.wasted)
+                .then_with(|| a.checksum.cmp(&b.checksum))
+        });
+        duplicates
+    }
+
+    fn in_bucket<'a>(&'a self, bucket: &Bucket<'_>) -> impl Iterator<Item = &'a Location> {
+        let from = (bucket.cloned(), Key::new(String::new()), String::new());
+        let bucket = bucket.cloned();
+        self.entries
+            .range(from..)
+            .map(|(location, _)| location)
+            .take_while(move |(b, _, _)| *b == bucket)
+    }
+
+    fn move_key(&mut self, from: &KeyLocation, to: &KeyLocation) {
+        let moves = self
+            .in_bucket(&from.bucket)
+            .filter(|(_, key, _)| key.in_subtree(&from.key))
+            .map(|x| {
+                let key = x.1.rebase(&from.key, &to.key);
+                (x.clone(), (to.bucket.cloned(), key, x.2.clone()))
+            })
+            .collect();
+        self.relocate(moves);
+    }
+
+    fn relocate(&mut self, moves: Vec<(Location, Location)>) {
+        let moved = moves
+            .into_iter()
+            .filter_map(|(from, to)| self.remove(&from).map(|indexed| (to, indexed)))
+            .collect::<Vec<_>>();
+
+        for (to, mut indexed) in moved {
+            indexed.extension = extension(&to.2);
+            self.remove(&to);
+            self.insert(to, indexed);
+        }
+    }
+
+    fn insert(&mut self, location: Location, indexed: Indexed) {
+        self.by_checksum
+            .entry(indexed.checksum.clone())
+            .or_default()
+            .insert(location.clone());
+        if let Some(extension) = indexed.extension.as_ref() {
+            self.by_extension
+                .entry(extension.clone())
+                .or_default()
+                .insert(location.clone());
+        }
+        self.entries.insert(location, indexed);
+    }
+
+    fn remove(&mut self, location: &Location) -> Option<Indexed> {
+        let indexed = self.entries.remove(location)?;
+        unlink(&mut self.by_checksum, &indexed.checksum, location);
+        if let Some(extension) = indexed.extension.as_ref() {
+            unlink(&mut self.by_extension, extension, location);
+        }
+        Some(indexed)
+    }
+}
+
+fn unlink(index: &mut HashMap<String, BTreeSet<Location>>, value: &str, location: &Location) {
+    if let Some(locations) = index.get_mut(value) {
+        locations.remove(location);
+        if locations.is_empty() {
+            index.remove(value);
+        }
+    }
+}
+
+fn object_location((bucket, key, file_name): &Location) -> ObjectLocation {
+    ObjectLocation {
+        bucket: bucket.cloned(),
+        key: key.cloned(),
+        file_name: file_name.clone(),
+    }
+}
+
+fn location(value: &ObjectLocation) -> Location {
+    (
+        value.bucket.cloned(),
+        value.key.cloned(),
+        value.file_name.clone(),
+    )
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::bucket::{
+    Bucket, Cowed,
+    bucket_map::{BucketMap, KeyEntry},
+    key::Segment,
+    object::Object,
+};
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> Option<Self> {
+        let md = std::fs::metadata(path).ok()?;
+        Some(Self {
+            dev: md.dev(),
+            ino: md.ino(),
+            mtime: md.mtime(),
+            mtime_nsec: md.mtime_nsec(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+    root: PathBuf,
+    pub buckets: BTreeMap<Bucket<'static>, SnapshotEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SnapshotEntry {
+    pub fingerprint: Option<Fingerprint>,
+    pub objects: Vec<Object>,
+    pub keys: BTreeMap<Segment<'static>, SnapshotEntry>,
+}
+
+impl From<&KeyEntry> for SnapshotEntry {
+    fn from(value: &KeyEntry) -> Self {
+        Self {
+            fingerprint: value.fingerprint,
+            objects: value.objects.iter().flatten().cloned().collect(),
+            keys: value
+                .keys
+                .iter()
+                .flatten()
+                .map(|(seg, entry)| (seg.cloned(), entry.into()))
+                .collect(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub async fn capture(tree: &BucketMap) -> Self {
+        let mut buckets = BTreeMap::new();
+        for (bucket, entry) in tree.buckets() {
+            buckets.insert(bucket, (&*entry.read().await).into());
+        }
+
+        Self {
+            version: SNAPSHOT_VERSION,
+            root: tree.path().to_path_buf(),
+            buckets,
+        }
+    }
+
+    pub async fn load(path: &Path, root: &Path) -> Option<Self> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(er) => {
+                tracing::info!("[ Snapshot ] {path:?} not loaded: {er}");
+                return None;
+            }
+        };
+
+        let snapshot = match serde_json::from_slice::<Self>(&bytes) {
+            Ok(snapshot) => snapshot,
+            Err(er) => {
+                tracing::warn!("[ Snapshot ] {path:?} is corrupt: {er}");
+                return None;
+            }
+        };
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            tracing::warn!(
+                "[ Snapshot ] {path:?} has version {}, expected {SNAPSHOT_VERSION}",
+                snapshot.version
+            );
+            return None;
+        }
+
+        if snapshot.root != root {
+            tracing::warn!(
+                "[ Snapshot ] {path:?} belongs to {:?}, not {root:?}",
+                snapshot.root
+            );
+            return None;
+        }
+
+        tracing::info!("[ Snapshot ] Loaded {path:?}");
+        Some(snapshot)
+    }
+
+    pub async fn save(&self, path: &Path) -> std::io::Result<()> {
+        let bytes = serde_json::to_vec(self)?;
+        let tmp = path.with_extension("tmp");
+
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        tokio::fs::rename(&tmp, path).await?;
+
+        if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+            tokio::fs::File::open(parent).await?.sync_all().await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn schedule(state: Arc<BucketMap>, path: PathBuf, every: Duration) {
+    tokio::spawn(async move {
+        save(&state, &path).await;
+        if every.is_zero() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(every);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            save(&state, &path).await;
+        }
+    });
+}
+
+async fn save(state: &BucketMap, path: &Path) {
+    let snapshot = Snapshot::capture(state).await;
+    match snapshot.save(path).await {
+        Ok(()) => tracing::info!("[ Snapshot ] Saved {path:?}"),
+        Err(er) => tracing::error!("[ Snapshot ] {path:?} not saved: {er}"),
+    }
+}
@@ -0,0 +1,30 @@
+use serde::Serialize;
+use time::{OffsetDateTime, serde::rfc3339::option};
+
+use crate::bucket::objects::Objects;
+
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct Usage {
+    pub total_size: i64,
+    pub object_count: usize,
+    #[serde(with = "option")]
+    pub last_modified: Option<OffsetDateTime>,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.total_size += other.total_size;
+        self.object_count += other.object_count;
+        self.last_modified = self.last_modified.max(other.last_modified);
+    }
+}
+
+impl From<&Objects> for Usage {
+    fn from(value: &Objects) -> Self {
+        Self {
+            total_size: value.total_size(),
+            object_count: value.len(),
+            last_modified: value.last_modified(),
+        }
+    }
+}
@@ -12,6 +12,14 @@ pub struct Args {
     #[arg(long, value_enum, default_value = "event")]
     pub watcher: TypeWatcher,
 
+    #[arg(
+        long = "watcher-interval",
+        env = "WATCHER_INTERVAL",
+        help = "Milliseconds between scans when using the poll watcher",
+        default_value = "2000"
+    )]
+    pub watcher_interval: u64,
+
     #[arg(long = "watcher-path", env = "ROOT_PATH")]
     pub watcher_path: PathBuf,
 
@@ -32,20 +40,37 @@ pub struct Args {
     )]
     pub grpc_auth_server: Endpoint,
 
+    #[arg(
+        long = "md-store",
+        env = "MD_STORE",
+        value_enum,
+        help = "Backend used to store object metadata",
+        default_value = "mongo"
+    )]
+    pub md_store: TypeMetadataStore,
+
     #[arg(long = "md-host", env = "MD_DATABASE_HOST")]
-    pub md_host: String,
+    pub md_host: Option<String>,
 
     #[arg(long = "md-port", env = "MD_DATABASE_PORT")]
-    pub md_port: u16,
+    pub md_port: Option<u16>,
 
     #[arg(long = "md-username", env = "MD_DATABASE_USERNAME")]
-    pub md_username: String,
+    pub md_username: Option<String>,
 
     #[arg(long = "md-password", env = "MD_DATABASE_PASSWORD")]
-    pub md_pass: String,
+    pub md_pass: Option<String>,
 
     #[arg(long = "md-database-name", env = "MD_DATABASE_NAME")]
-    pub md_database: String,
+    pub md_database: Option<String>,
+
+    #[arg(
+        long = "md-sqlite-path",
+        env = "MD_SQLITE_PATH",
+        help = "Database file used by the sqlite metadata store",
+        default_value = "directory.db"
+    )]
+    pub md_sqlite_path: PathBuf,
 
     #[arg(long = "pki-dir", env = "PKI_DIR")]
     pub pki_dir: String,
@@ -56,6 +81,141 @@ pub struct Args {
         default_value = "[::1]:50052"
     )]
     pub grpc_endpoint: SocketAddr,
+
+    #[arg(long = "ws-buffer", env = "WS_BUFFER", default_value = "32")]
+    pub ws_buffer: usize,
+
+    #[arg(
+        long = "ws-delivery-policy",
+        env = "WS_DELIVERY_POLICY",
+        value_enum,
+        default_value = "drop-oldest"
+    )]
+    pub ws_delivery_policy: TypeDeliveryPolicy,
+
+    #[arg(
+        long = "ws-max-missed",
+        env = "WS_MAX_MISSED",
+        help = "Missed messages before a slow subscriber is disconnected",
+        default_value = "64"
+    )]
+    pub ws_max_missed: usize,
+
+    #[arg(
+        long = "ws-ping-interval",
+        env = "WS_PING_INTERVAL",
+        help = "Seconds between pings, 0 disables them",
+        default_value = "30"
+    )]
+    pub ws_ping_interval: u64,
+
+    #[arg(
+        long = "ws-pong-timeout",
+        env = "WS_PONG_TIMEOUT",
+        help = "Seconds to wait for a pong before closing the connection",
+        default_value = "10"
+    )]
+    pub ws_pong_timeout: u64,
+
+    #[arg(
+        long = "ws-idle-timeout",
+        env = "WS_IDLE_TIMEOUT",
+        help = "Seconds without frames from the peer before closing the connection, 0 disables it",
+        default_value = "300"
+    )]
+    pub ws_idle_timeout: u64,
+
+    #[arg(
+        long = "change-log-size",
+        env = "CHANGE_LOG_SIZE",
+        help = "Number of recent changes kept so websocket clients can resume",
+        default_value = "1024"
+    )]
+    pub change_log_size: usize,
+
+    #[arg(
+        long = "change-log-persist",
+        env = "CHANGE_LOG_PERSIST",
+        help = "Store the change log in the metadata database so sequence numbers survive restarts"
+    )]
+    pub change_log_persist: bool,
+
+    #[arg(
+        long = "permission-cache-ttl",
+        env = "PERMISSION_CACHE_TTL",
+        help = "Seconds a user's bucket permissions stay cached, 0 disables the cache",
+        default_value = "30"
+    )]
+    pub permission_cache_ttl: u64,
+
+    #[arg(
+        long = "settle-window",
+        env = "SETTLE_WINDOW",
+        help = "Milliseconds a file's size and mtime must stay unchanged before it's registered",
+        default_value = "2000"
+    )]
+    pub settle_window: u64,
+
+    #[arg(
+        long = "reconcile-interval",
+        env = "RECONCILE_INTERVAL",
+        help = "Seconds between reconciliations of disk, tree and database, 0 disables it",
+        default_value = "0"
+    )]
+    pub reconcile_interval: u64,
+
+    #[arg(
+        long = "reconcile-fix",
+        env = "RECONCILE_FIX",
+        help = "Apply the changes found by scheduled reconciliations instead of only reporting them"
+    )]
+    pub reconcile_fix: bool,
+
+    #[arg(
+        long = "snapshot-path",
+        env = "SNAPSHOT_PATH",
+        help = "File where the tree snapshot used for fast startup is kept"
+    )]
+    pub snapshot_path: Option<PathBuf>,
+
+    #[arg(
+        long = "snapshot-interval",
+        env = "SNAPSHOT_INTERVAL",
+        help = "Seconds between tree snapshots, 0 only writes it after startup",
+        default_value = "300"
+    )]
+    pub snapshot_interval: u64,
+
+    #[arg(
+        long = "verify-checksums",
+        env = "VERIFY_CHECKSUMS",
+        help = "Hash every file during build and reconciliation instead of trusting unchanged size, mtime and inode"
+    )]
+    pub verify_checksums: bool,
+
+    #[arg(
+        long = "build-hash-concurrency",
+        env = "BUILD_HASH_CONCURRENCY",
+        help = "Files hashed at the same time during the startup build, 0 uses one per CPU",
+        default_value = "0"
+    )]
+    pub build_hash_concurrency: usize,
+
+    #[arg(
+        long = "build-db-concurrency",
+        env = "BUILD_DB_CONCURRENCY",
+        help = "Metadata store round trips in flight during the startup build",
+        default_value = "16"
+    )]
+    pub build_db_concurrency: usize,
+
+    #[arg(
+        long = "object-index",
+        env = "OBJECT_INDEX",
+        help = "Secondary indexes kept for the objects of every key, comma separated",
+        value_delimiter = ','
+    )]
+    pub object_index: Vec<TypeObjectIndex>,
 }
 
 #[derive(Clone, ValueEnum)]
@@ -64,6 +224,26 @@ pub enum TypeWatcher {
     Event,
 }
 
+#[derive(Clone, ValueEnum)]
+pub enum TypeMetadataStore {
+    Mongo,
+    Memory,
+    Sqlite,
+}
+
+#[derive(Clone, PartialEq, ValueEnum)]
+pub enum TypeObjectIndex {
+    Checksum,
+    Modified,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum TypeDeliveryPolicy {
+    DropOldest,
+    DropNewest,
+    Disconnect,
+}
+
 #[derive(Clone, ValueEnum)]
 pub enum LogLebel {
     Trace,
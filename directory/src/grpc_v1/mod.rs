@@ -1,27 +1,78 @@
+pub mod permission_cache;
+
 mod proto {
     tonic::include_proto!("info");
 }
 
-use crate::manager::Change;
-pub use proto::{AllowedBucketReq, BucketReq, Permissions, info_client::InfoClient};
-use proto::{BucketReply, UserByIdReq, UserReply};
-use tokio::sync::mpsc::UnboundedSender;
-use tonic::transport::{Channel, Endpoint};
+use permission_cache::{PermissionCache, PermissionChange};
+pub use proto::{
+    AllowedBucketReq, BucketEvent, BucketOperation, BucketReq, Permissions, Role, UserReply,
+    info_client::InfoClient,
+};
+use proto::{BucketReply, RegisterBucketReq, UserByIdReq, WatchBucketsReq, WatchPermissionsReq};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use tonic::{
+    Status, Streaming,
+    transport::{Channel, Endpoint},
+};
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct ConnectionAuthMS {
     inner: InfoClient<Channel>,
-    notify_changes: UnboundedSender<Change>,
+    cache: Arc<PermissionCache>,
 }
 
 impl ConnectionAuthMS {
-    pub async fn new(endpoint: Endpoint, tx_shc: UnboundedSender<Change>) -> Self {
+    pub async fn new(endpoint: Endpoint, cache_ttl: Duration) -> Self {
         Self {
             inner: InfoClient::new(endpoint.connect_lazy()),
-            notify_changes: tx_shc,
+            cache: Arc::new(PermissionCache::new(cache_ttl)),
         }
     }
+
+    pub fn watch(&self) {
+        let mut inner = self.inner.clone();
+        let cache = self.cache.clone();
+
+        tokio::spawn(async move {
+            let mut disconnected = false;
+            loop {
+                match inner.watch_permissions(WatchPermissionsReq {}).await {
+                    Ok(stream) => {
+                        tracing::info!("[ ConnectionAuthMS ] Watching permission changes");
+                        if std::mem::take(&mut disconnected) {
+                            cache.invalidate(PermissionChange {
+                                user_id: None,
+                                bucket: None,
+                            });
+                        }
+                        let mut stream = stream.into_inner();
+                        loop {
+                            match stream.message().await {
+                                Ok(Some(ev)) => cache.invalidate(ev.into()),
+                                Ok(None) => break,
+                                Err(er) => {
+                                    tracing::error!("[ ConnectionAuthMS ] watch error: {er}");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(er) => tracing::error!("[ ConnectionAuthMS ] watch error: {er}"),
+                }
+
+                disconnected = true;
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    pub fn changes(&self) -> broadcast::Receiver<PermissionChange> {
+        self.cache.changes()
+    }
+
     pub async fn get_buckets(&self) -> Option<BucketReply> {
         self.inner
             .clone()
@@ -31,6 +82,45 @@ impl ConnectionAuthMS {
             .ok()
     }
 
+    pub async fn registered_buckets(&self) -> Option<BucketReply> {
+        self.inner
+            .clone()
+            .list_buckets(BucketReq { name: None })
+            .await
+            .map(|x| x.into_inner())
+            .inspect_err(|er| tracing::error!("[ ConnectionAuthMS ] list buckets error: {er}"))
+            .ok()
+    }
+
+    pub async fn bucket_exists(&self, name: &str) -> Option<bool> {
+        self.inner
+            .clone()
+            .list_buckets(BucketReq {
+                name: Some(name.to_string()),
+            })
+            .await
+            .map(|x| !x.into_inner().buckets.is_empty())
+            .ok()
+    }
+
+    pub async fn register_bucket(&self, name: &str) -> Result<bool, Status> {
+        self.inner
+            .clone()
+            .register_bucket(RegisterBucketReq {
+                name: name.to_string(),
+            })
+            .await
+            .map(|x| x.into_inner().created)
+    }
+
+    pub async fn watch_buckets(&self) -> Result<Streaming<BucketEvent>, Status> {
+        self.inner
+            .clone()
+            .watch_buckets(WatchBucketsReq {})
+            .await
+            .map(|x| x.into_inner())
+    }
+
     pub async fn allowed(&self, id: Uuid, name: String, permission: Permissions) -> bool {
         self.inner
             .clone()
@@ -44,14 +134,44 @@ impl ConnectionAuthMS {
             .unwrap_or_default()
     }
 
-    pub async fn buckets_user(&self, id: Uuid) -> Option<UserReply> {
+    pub async fn buckets_user(&self, id: Uuid) -> Option<Arc<UserReply>> {
+        self.fetch_user(id).await.ok()
+    }
+
+    async fn fetch_user(&self, id: Uuid) -> Result<Arc<UserReply>, Status> {
+        if let Some(reply) = self.cache.get(&id) {
+            return Ok(reply);
+        }
+
         self.inner
             .clone()
             .user_by_id(UserByIdReq {
                 id: id.as_bytes().to_vec(),
             })
             .await
-            .map(|x| x.into_inner())
-            .ok()
+            .map(|x| self.cache.insert(id, x.into_inner()))
+            .inspect_err(|er| tracing::error!("[ ConnectionAuthMS ] user {id} error: {er}"))
+    }
+
+    pub async fn is_allowed(
+        &self,
+        id: Uuid,
+        bucket: Option<&str>,
+        permission: Permissions,
+    ) -> Result<bool, Status> {
+        let user = self.fetch_user(id).await?;
+        Ok(user.is_admin() || bucket.is_some_and(|bucket| user.can(bucket, permission)))
+    }
+}
+
+impl UserReply {
+    pub fn is_admin(&self) -> bool {
+        matches!(self.role(), Role::SuperUs | Role::Administrator)
+    }
+
+    pub fn can(&self, bucket: &str, permission: Permissions) -> bool {
+        self.buckets
+            .iter()
+            .any(|x| x.name == bucket && x.permissions().any(|x| x == permission))
     }
 }
@@ -0,0 +1,94 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::{sync::broadcast, time::Instant};
+use uuid::Uuid;
+
+use super::proto::{PermissionEvent, UserReply};
+
+#[derive(Debug, Clone)]
+pub struct PermissionChange {
+    pub user_id: Option<Uuid>,
+    pub bucket: Option<String>,
+}
+
+impl PermissionChange {
+    pub fn affects(&self, user_id: Uuid) -> bool {
+        self.user_id.is_none_or(|x| x == user_id)
+    }
+}
+
+impl From<PermissionEvent> for PermissionChange {
+    fn from(value: PermissionEvent) -> Self {
+        Self {
+            user_id: value.user_id.and_then(|x| Uuid::from_slice(&x).ok()),
+            bucket: value.bucket,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedUser {
+    reply: Arc<UserReply>,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+pub struct PermissionCache {
+    users: DashMap<Uuid, CachedUser>,
+    ttl: Duration,
+    changes: broadcast::Sender<PermissionChange>,
+}
+
+impl PermissionCache {
+    pub fn new(ttl: Duration) -> Self {
+        let (changes, _) = broadcast::channel(256);
+        Self {
+            users: DashMap::new(),
+            ttl,
+            changes,
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Arc<UserReply>> {
+        let entry = self.users.get(id)?;
+        if entry.expires > Instant::now() {
+            return Some(entry.reply.clone());
+        }
+        drop(entry);
+        self.users.remove(id);
+        None
+    }
+
+    pub fn insert(&self, id: Uuid, reply: UserReply) -> Arc<UserReply> {
+        let reply = Arc::new(reply);
+        if !self.ttl.is_zero() {
+            self.users.insert(
+                id,
+                CachedUser {
+                    reply: reply.clone(),
+                    expires: Instant::now() + self.ttl,
+                },
+            );
+        }
+        reply
+    }
+
+    pub fn invalidate(&self, change: PermissionChange) {
+        match (&change.user_id, &change.bucket) {
+            (Some(user_id), _) => {
+                self.users.remove(user_id);
+            }
+            (None, Some(bucket)) => self
+                .users
+                .retain(|_, x| !x.reply.buckets.iter().any(|x| &x.name == bucket)),
+            (None, None) => self.users.clear(),
+        }
+        tracing::debug!("[ PermissionCache ] Invalidate {change:?}");
+        _ = self.changes.send(change);
+    }
+
+    pub fn changes(&self) -> broadcast::Receiver<PermissionChange> {
+        self.changes.subscribe()
+    }
+}
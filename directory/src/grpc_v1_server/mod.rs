@@ -3,26 +3,39 @@ mod proto {
 }
 
 pub use proto::directory_server::DirectoryServer;
-use proto::{FileNameReply, FileNameReq, directory_server::Directory};
+use proto::{
+    DuplicateReply, DuplicateReq, FileNameReply, FileNameReq, SearchHit, SearchReply, SearchReq,
+    directory_server::Directory,
+};
 use std::{net::SocketAddr, path::PathBuf, sync::Arc};
-use tokio::sync::RwLock;
 use tonic::{async_trait, transport::Server};
+use uuid::Uuid;
 
 use crate::{
-    bucket::{Bucket, bucket_map::BucketMap, key::Key},
+    bucket::{
+        Bucket,
+        bucket_map::BucketMap,
+        key::Key,
+        object::OwnerFile,
+        search::{Hit, SearchQuery},
+    },
+    grpc_v1::{ConnectionAuthMS, Permissions},
     grpc_v1_server,
+    manager::ObjectLocation,
 };
 
 pub struct BucketGrpcSrv {
-    map: Arc<RwLock<BucketMap>>,
+    map: Arc<BucketMap>,
     path: PathBuf,
+    auth: ConnectionAuthMS,
 }
 
 impl BucketGrpcSrv {
-    pub fn new(map: Arc<RwLock<BucketMap>>, root_path: impl Into<PathBuf>) -> Self {
+    pub fn new(map: Arc<BucketMap>, root_path: impl Into<PathBuf>, auth: ConnectionAuthMS) -> Self {
         Self {
             map,
             path: root_path.into(),
+            auth,
         }
     }
 }
@@ -55,10 +68,9 @@ impl Directory for BucketGrpcSrv {
 
         match self
             .map
-            .read()
-            .await
             .get_object(&bucket, &key, &name)
-            .map(|x| x.file_name.clone())
+            .await
+            .map(|x| x.file_name)
         {
             Some(file_name) => Ok(tonic::Response::new(FileNameReply { file_name })),
             None => Err(tonic::Status::not_found(format!(
@@ -70,6 +82,66 @@ impl Directory for BucketGrpcSrv {
         }
     }
 
+    async fn search(
+        &self,
+        request: tonic::Request<SearchReq>,
+    ) -> Result<tonic::Response<SearchReply>, tonic::Status> {
+        let request = request.into_inner();
+        let user_id = Uuid::parse_str(&request.user_id)
+            .map_err(|_| tonic::Status::invalid_argument("Invalid user_id"))?;
+        let query = SearchQuery::try_from(request).map_err(tonic::Status::invalid_argument)?;
+
+        let Some(user) = self.auth.buckets_user(user_id).await else {
+            return Err(tonic::Status::permission_denied(format!(
+                "User {user_id} not found"
+            )));
+        };
+
+        let page = self
+            .map
+            .search(&query, |bucket| {
+                user.is_admin() || user.can(bucket.name(), Permissions::Read)
+            })
+            .await;
+
+        Ok(tonic::Response::new(SearchReply {
+            hits: page.hits.into_iter().map(Into::into).collect(),
+            next_cursor: page.next_cursor,
+        }))
+    }
+
+    async fn check_duplicate(
+        &self,
+        request: tonic::Request<DuplicateReq>,
+    ) -> Result<tonic::Response<DuplicateReply>, tonic::Status> {
+        let DuplicateReq {
+            user_id,
+            checksum,
+            bucket,
+        } = request.into_inner();
+        let user_id = Uuid::parse_str(&user_id)
+            .map_err(|_| tonic::Status::invalid_argument("Invalid user_id"))?;
+
+        let Some(user) = self.auth.buckets_user(user_id).await else {
+            return Err(tonic::Status::permission_denied(format!(
+                "User {user_id} not found"
+            )));
+        };
+
+        let locations = self
+            .map
+            .locate(&checksum, |name| {
+                bucket.as_ref().is_none_or(|x| name == x)
+                    && (user.is_admin() || user.can(name.name(), Permissions::Read))
+            })
+            .await;
+
+        Ok(tonic::Response::new(DuplicateReply {
+            exists: !locations.is_empty(),
+            locations: locations.into_iter().map(Into::into).collect(),
+        }))
+    }
+
     async fn create_object(
         &self,
         request: tonic::Request<FileNameReq>,
@@ -91,3 +163,65 @@ impl Directory for BucketGrpcSrv {
         }
     }
 }
+
+impl TryFrom<SearchReq> for SearchQuery {
+    type Error = String;
+
+    fn try_from(value: SearchReq) -> Result<Self, Self::Error> {
+        let timestamp = |x: Option<i64>| x.map(SearchQuery::timestamp).transpose();
+
+        Ok(Self {
+            name: match (value.name, value.regex) {
+                (_, Some(regex)) => Some(SearchQuery::regex(&regex)?),
+                (Some(glob), None) => Some(SearchQuery::glob(&glob)?),
+                (None, None) => None,
+            },
+            extension: value.extension.as_deref().map(SearchQuery::extension),
+            size_min: value.size_min,
+            size_max: value.size_max,
+            modified_after: timestamp(value.modified_after)?,
+            modified_before: timestamp(value.modified_before)?,
+            created_after: timestamp(value.created_after)?,
+            created_before: timestamp(value.created_before)?,
+            owner: value.owner.as_deref().map(SearchQuery::owner),
+            checksum: value.checksum,
+            limit: match value.limit {
+                Some(limit) => SearchQuery::limit(limit)?,
+                None => SearchQuery::default().limit,
+            },
+            cursor: value
+                .cursor
+                .as_deref()
+                .map(SearchQuery::cursor)
+                .transpose()?,
+        })
+    }
+}
+
+impl From<Hit> for SearchHit {
+    fn from(value: Hit) -> Self {
+        Self {
+            bucket: value.bucket.into_inner(),
+            key: value.key.inner(),
+            file_name: value.file_name,
+            size: value.size,
+            checksum: value.checksum,
+            owner: match value.owner {
+                OwnerFile::System => "system".to_string(),
+                OwnerFile::User(user) => user,
+            },
+            modified: value.modified.map(|x| x.unix_timestamp()),
+            created: value.created.map(|x| x.unix_timestamp()),
+        }
+    }
+}
+
+impl From<ObjectLocation> for proto::ObjectLocation {
+    fn from(value: ObjectLocation) -> Self {
+        Self {
+            bucket: value.bucket.into_inner(),
+            key: value.key.inner(),
+            file_name: value.file_name,
+        }
+    }
+}
@@ -8,6 +8,7 @@ use utils::{
     claim::Claim,
     middleware::{IntoLayer, Layer},
 };
+use uuid::Uuid;
 
 pub struct Auth;
 
@@ -43,6 +44,12 @@ where
         &self,
         mut req: http::Request<ReqBody>,
     ) -> impl Future<Output = Result<http::Response<Self::Response>, Self::Error>> {
+        if req.uri().path() == "/ready" {
+            return AuthFuture::Pass {
+                f: self.inner.call(req),
+            };
+        }
+
         if req
             .headers()
             .get(http::header::CONTENT_TYPE)
@@ -55,7 +62,7 @@ where
             return AuthFuture::Aunauthorized;
         };
 
-        let claims = match JwtHandle::verify_token::<Claim<i32>>(&token) {
+        let claims = match JwtHandle::verify_token::<Claim<Uuid>>(&token) {
             Ok(claims) => claims,
             Err(err) => {
                 tracing::error!("[Midleware jwt] {err}");
@@ -1,7 +1,9 @@
 pub mod auth_layer;
 pub mod error;
+pub mod ready;
 use crate::{
-    bucket::{Bucket, fhs::Fhs, key::Key},
+    bucket::{Bucket, fhs::Fhs, key::Key, listing::Listing, search::SearchQuery},
+    grpc_v1::Permissions,
     handlers::error::ResponseError,
     state::State,
 };
@@ -13,7 +15,10 @@ use hyper::{
     body::{Bytes, Incoming},
 };
 use serde_json::json;
-use std::{convert::Infallible, sync::Arc};
+use std::{collections::BTreeMap, convert::Infallible, sync::Arc};
+use url::form_urlencoded;
+use utils::claim::Claim;
+use uuid::Uuid;
 
 type TypeState = Arc<State>;
 
@@ -34,25 +39,79 @@ pub async fn entry(mut req: Request<Incoming>) -> Result<Response<Full<Bytes>>,
         }
 
         let state = req.extensions().get::<TypeState>().unwrap().clone();
+        let user_id = *req.extensions().get::<Claim<Uuid>>().unwrap().sub();
 
         let pair = if path.is_empty() {
-            todo!("I need to check if the user logged is admin");
             None
         } else {
             let (path, key) = path.split_once("/").unwrap_or((path, "."));
             Some((Bucket::new_unchecked(path), Key::new(key)))
         };
 
+        if (pair.is_some() || hyper_tungstenite::is_upgrade_request(&req))
+            && !state
+                .bucket(
+                    user_id,
+                    pair.as_ref().map(|(bucket, _)| bucket.name()),
+                    Permissions::Read,
+                )
+                .await
+        {
+            tracing::debug!("[ entry ] {user_id} isn't allowed to read {path:?}");
+            return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+        }
+
         if hyper_tungstenite::is_upgrade_request(&req) {
             let (res, ws) = hyper_tungstenite::upgrade(&mut req, None).unwrap();
             let (bucket, key) = pair.unzip();
-            state.write().await.subscriber(bucket, key, ws).await;
+            let resume_from = req.uri().query().and_then(|x| {
+                x.split('&')
+                    .find_map(|x| x.strip_prefix("resume_from="))
+                    .and_then(|x| x.parse().ok())
+            });
+            state
+                .add_client(user_id, bucket, key, resume_from, ws)
+                .await;
             Ok(res)
         } else {
-            let state = state.read().await;
-            let body: Fhs<'_> = match pair.as_ref() {
-                Some((bucket, key)) => state.get_entry(bucket, key).unwrap().into(),
-                None => state.get_buckets().into_iter().collect::<Vec<_>>().into(),
+            let user = match pair {
+                Some(_) => None,
+                None => state.user(user_id).await,
+            };
+
+            let listing = match Listing::parse(req.uri().query().unwrap_or_default()) {
+                Ok(listing) => listing,
+                Err(er) => return Ok(ResponseError::new(er, StatusCode::BAD_REQUEST).into()),
+            };
+
+            let root = match pair.as_ref() {
+                Some((bucket, _)) => state.read_bucket(bucket).await,
+                None => None,
+            };
+            let buckets = match pair {
+                Some(_) => Vec::new(),
+                None => state.get_buckets(),
+            };
+
+            let body: Fhs<'_> = match (pair.as_ref(), root.as_deref()) {
+                (Some((_, key)), Some(root)) => {
+                    let Some(entry) = root.get(key) else {
+                        return Ok(ResponseError::status(StatusCode::NOT_FOUND).into());
+                    };
+                    Fhs::listed(None, entry, &listing, listing.depth, true)
+                }
+                (Some(_), None) => {
+                    return Ok(ResponseError::status(StatusCode::NOT_FOUND).into());
+                }
+                (None, _) => buckets
+                    .iter()
+                    .filter(|x| {
+                        user.as_ref().is_some_and(|user| {
+                            user.is_admin() || user.can(x.name(), Permissions::Read)
+                        })
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
             };
 
             Ok(Response::builder()
@@ -61,6 +120,215 @@ pub async fn entry(mut req: Request<Incoming>) -> Result<Response<Full<Bytes>>,
                 .body(Full::new(Bytes::from(json!(body).to_string())))
                 .unwrap_or_default())
         }
+    } else if let Some(bucket) = path
+        .strip_prefix("/usage")
+        .filter(|path| path.is_empty() || path.starts_with('/'))
+        .map(|path| path.strip_prefix("/").unwrap_or(path))
+    {
+        if req.method() != http::Method::GET {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Full::default())
+                .unwrap_or_default());
+        }
+
+        let state = req.extensions().get::<TypeState>().unwrap().clone();
+        let user_id = *req.extensions().get::<Claim<Uuid>>().unwrap().sub();
+
+        let body = if bucket.is_empty() {
+            let Some(user) = state.user(user_id).await else {
+                return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+            };
+
+            let mut buckets = Vec::new();
+            for (bucket, entry) in state.buckets() {
+                if user.is_admin() || user.can(bucket.name(), Permissions::Read) {
+                    buckets.push(json!({ "bucket": bucket, "usage": entry.read().await.usage }));
+                }
+            }
+            json!(buckets)
+        } else {
+            let bucket = Bucket::new_unchecked(bucket);
+            if !state
+                .bucket(user_id, Some(bucket.name()), Permissions::Read)
+                .await
+            {
+                tracing::debug!("[ entry ] {user_id} isn't allowed to read {bucket} usage");
+                return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+            }
+
+            let Some(root) = state.read_bucket(&bucket).await else {
+                return Ok(ResponseError::status(StatusCode::NOT_FOUND).into());
+            };
+            let keys = root
+                .keys
+                .iter()
+                .flatten()
+                .map(|(seg, entry)| (seg.as_ref(), entry.usage))
+                .collect::<BTreeMap<_, _>>();
+            json!({ "bucket": bucket, "usage": root.usage, "keys": keys })
+        };
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body.to_string())))
+            .unwrap_or_default())
+    } else if path == "/search" {
+        if req.method() != http::Method::GET {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Full::default())
+                .unwrap_or_default());
+        }
+
+        let state = req.extensions().get::<TypeState>().unwrap().clone();
+        let user_id = *req.extensions().get::<Claim<Uuid>>().unwrap().sub();
+
+        let Some(user) = state.user(user_id).await else {
+            return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+        };
+
+        let query = match SearchQuery::parse(req.uri().query().unwrap_or_default()) {
+            Ok(query) => query,
+            Err(er) => return Ok(ResponseError::new(er, StatusCode::BAD_REQUEST).into()),
+        };
+
+        let page = state
+            .search(&query, |bucket| {
+                user.is_admin() || user.can(bucket.name(), Permissions::Read)
+            })
+            .await;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(json!(page).to_string())))
+            .unwrap_or_default())
+    } else if let Some(bucket) = path
+        .strip_prefix("/duplicates")
+        .filter(|path| path.is_empty() || path.starts_with('/'))
+        .map(|path| path.strip_prefix("/").unwrap_or(path))
+    {
+        if req.method() != http::Method::GET {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Full::default())
+                .unwrap_or_default());
+        }
+
+        let state = req.extensions().get::<TypeState>().unwrap().clone();
+        let user_id = *req.extensions().get::<Claim<Uuid>>().unwrap().sub();
+
+        let Some(user) = state.user(user_id).await else {
+            return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+        };
+
+        let bucket = (!bucket.is_empty()).then(|| Bucket::new_unchecked(bucket));
+        if let Some(bucket) = bucket.as_ref()
+            && !user.is_admin()
+            && !user.can(bucket.name(), Permissions::Read)
+        {
+            tracing::debug!("[ entry ] {user_id} isn't allowed to read {bucket} duplicates");
+            return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+        }
+
+        let allowed = |name: &Bucket<'_>| {
+            bucket.as_ref().is_none_or(|x| x == name)
+                && (user.is_admin() || user.can(name.name(), Permissions::Read))
+        };
+
+        let checksum = req.uri().query().and_then(|x| {
+            form_urlencoded::parse(x.as_bytes())
+                .find_map(|(name, value)| (name == "checksum").then_some(value))
+                .filter(|x| !x.is_empty())
+        });
+
+        let body = match checksum {
+            Some(checksum) => {
+                let locations = state.locate(&checksum, allowed).await;
+                json!({ "checksum": checksum, "exists": !locations.is_empty(), "locations": locations })
+            }
+            None => {
+                let groups = state.duplicates(allowed).await;
+                let wasted = groups.iter().map(|x| x.wasted).sum::<i64>();
+                json!({ "bucket": bucket, "wasted": wasted, "groups": groups })
+            }
+        };
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body.to_string())))
+            .unwrap_or_default())
+    } else if path == "/ready" {
+        if req.method() != http::Method::GET {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Full::default())
+                .unwrap_or_default());
+        }
+
+        let state = req.extensions().get::<TypeState>().unwrap().clone();
+        Ok(ready::response(state.progress()))
+    } else if path == "/admin/reconcile" || path.starts_with("/admin/reconcile/") {
+        let job = path
+            .strip_prefix("/admin/reconcile/")
+            .filter(|x| !x.is_empty());
+        let allowed = match job {
+            Some(_) => http::Method::GET,
+            None => http::Method::POST,
+        };
+        if req.method() != allowed {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Full::default())
+                .unwrap_or_default());
+        }
+
+        let state = req.extensions().get::<TypeState>().unwrap().clone();
+        let user_id = *req.extensions().get::<Claim<Uuid>>().unwrap().sub();
+
+        if !state
+            .user(user_id)
+            .await
+            .is_some_and(|user| user.is_admin())
+        {
+            tracing::debug!("[ entry ] {user_id} isn't allowed to reconcile");
+            return Ok(ResponseError::status(StatusCode::FORBIDDEN).into());
+        }
+
+        if let Some(job) = job {
+            let Ok(id) = Uuid::parse_str(job) else {
+                return Ok(ResponseError::status(StatusCode::NOT_FOUND).into());
+            };
+
+            return match state.reconcile_job(id).await {
+                Some(job) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Full::new(Bytes::from(
+                        json!({ "job": id, "result": job }).to_string(),
+                    )))
+                    .unwrap_or_default()),
+                None => Ok(ResponseError::status(StatusCode::NOT_FOUND).into()),
+            };
+        }
+
+        let fix = req
+            .uri()
+            .query()
+            .is_some_and(|x| x.split('&').any(|x| x == "fix=true"));
+
+        match state.reconcile(fix).await {
+            Some(id) => Ok(Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::LOCATION, format!("/admin/reconcile/{id}"))
+                .body(Full::new(Bytes::from(json!({ "job": id }).to_string())))
+                .unwrap_or_default()),
+            None => Ok(ResponseError::status(StatusCode::INTERNAL_SERVER_ERROR).into()),
+        }
     } else {
         Ok(Response::builder()
             .status(StatusCode::BAD_REQUEST)
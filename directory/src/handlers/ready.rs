@@ -0,0 +1,73 @@
+use std::{convert::Infallible, sync::Arc};
+
+use http::{StatusCode, header};
+use http_body_util::Full;
+use hyper::{
+    Request, Response,
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+};
+use serde_json::json;
+use tokio::{net::TcpListener, task::JoinHandle};
+use utils::Io;
+
+use crate::bucket::progress::BuildProgress;
+
+pub fn response(progress: &BuildProgress) -> Response<Full<Bytes>> {
+    let report = progress.report();
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(json!(report).to_string())))
+        .unwrap_or_default()
+}
+
+pub fn serve_until_ready(
+    listener: TcpListener,
+    progress: Arc<BuildProgress>,
+) -> JoinHandle<TcpListener> {
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                _ = progress.wait_ready() => return listener,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(er) => {
+                        tracing::error!("[ Ready ] accept error: {er}");
+                        continue;
+                    }
+                },
+            };
+
+            let progress = progress.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<Incoming>| {
+                    let res = if req.uri().path() == "/ready" {
+                        response(&progress)
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::SERVICE_UNAVAILABLE)
+                            .body(Full::default())
+                            .unwrap_or_default()
+                    };
+                    async move { Ok::<_, Infallible>(res) }
+                });
+
+                if let Err(er) = http1::Builder::new()
+                    .keep_alive(false)
+                    .serve_connection(Io::new(stream), service)
+                    .await
+                {
+                    tracing::error!("[ Ready ] {er}");
+                }
+            });
+        }
+    })
+}
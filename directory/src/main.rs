@@ -12,16 +12,34 @@ pub mod ws;
 
 use crate::{
     actor::Actor,
-    bucket::bucket_map::BucketMap,
-    cli::Args,
+    bucket::{
+        bucket_map::BucketMap,
+        objects::ObjectIndexes,
+        progress::BuildProgress,
+        snapshot::{self, Snapshot},
+    },
+    cli::{Args, TypeDeliveryPolicy, TypeMetadataStore, TypeObjectIndex, TypeWatcher},
+    grpc_v1::ConnectionAuthMS,
     handlers::{auth_layer::Auth, entry},
-    manager::{Manager, watcher::event_watcher::EventWatcher},
-    state::{State, local_storage::LocalStorageBuild},
+    manager::{
+        Manager,
+        bucket_sync::BucketSync,
+        change_log::ChangeLog,
+        reconcile,
+        watcher::event_watcher::{Backend, EventWatcher},
+        websocket::{WebSocketConfig, observer::DeliveryPolicy},
+    },
+    state::{
+        State,
+        local_storage::{
+            LocalStorageBuild, MetadataStore, memory::MemoryStorage, sqlite::SqliteStorage,
+        },
+    },
 };
 use clap::Parser;
 use http::{Method, header};
 use hyper::{server::conn::http1, service::service_fn};
-use std::{collections::HashMap, env, sync::Arc};
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
 use tokio::{net::TcpListener, sync::RwLock};
 use tracing::Level;
 use tracing_subscriber::fmt;
@@ -36,19 +54,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     _ = dotenv::dotenv();
 
     let Args {
-        watcher: _,
+        watcher: type_watcher,
+        watcher_interval,
         watcher_path,
         listen,
         port,
         log_level,
-        grpc_auth_server: _,
+        grpc_auth_server,
+        md_store,
         md_host,
         md_port,
         md_username,
         md_pass,
         md_database,
+        md_sqlite_path,
         pki_dir: _,
         grpc_endpoint,
+        ws_buffer,
+        ws_delivery_policy,
+        ws_max_missed,
+        ws_ping_interval,
+        ws_pong_timeout,
+        ws_idle_timeout,
+        change_log_size,
+        change_log_persist,
+        permission_cache_ttl,
+        settle_window,
+        reconcile_interval,
+        reconcile_fix,
+        snapshot_path,
+        snapshot_interval,
+        verify_checksums,
+        build_hash_concurrency,
+        build_db_concurrency,
+        object_index,
     } = Args::parse();
 
     let tr = fmt().with_max_level(Level::from(log_level)).finish();
@@ -59,34 +98,126 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut http = http1::Builder::new();
     http.keep_alive(true);
 
-    let state = Arc::new(RwLock::new(BucketMap::new(
-        watcher_path.canonicalize().unwrap(),
-    )));
+    let mut tree = BucketMap::new(watcher_path.canonicalize().unwrap());
+    tree.set_verify_checksums(verify_checksums);
+    tree.set_build_concurrency(build_hash_concurrency, build_db_concurrency);
+    tree.set_object_indexes(ObjectIndexes {
+        checksum: object_index.contains(&TypeObjectIndex::Checksum),
+        modified: object_index.contains(&TypeObjectIndex::Modified),
+    });
+    let state = Arc::new(tree);
 
-    let ls = LocalStorageBuild::default()
-        .host(md_host)
-        .port(md_port)
-        .password(md_pass)
-        .username(md_username)
-        .database(md_database)
-        .build()
-        .await;
+    let progress = Arc::new(BuildProgress::default());
+    progress.clone().log_every(Duration::from_secs(5));
+    let booting = handlers::ready::serve_until_ready(listener, progress.clone());
 
-    let ls = Arc::new(ls);
-    state.write().await.build(ls.as_ref()).await;
+    let ls: Arc<dyn MetadataStore> = match md_store {
+        TypeMetadataStore::Mongo => {
+            let (Some(host), Some(port), Some(username), Some(pass), Some(database)) =
+                (md_host, md_port, md_username, md_pass, md_database)
+            else {
+                return Err("the mongo metadata store needs --md-host, --md-port, --md-username, --md-password and --md-database-name".into());
+            };
 
-    let path = state.read().await.path().to_path_buf();
-    grpc_v1_server::BucketGrpcSrv::new(state.clone(), path.clone()).run(grpc_endpoint);
+            Arc::new(
+                LocalStorageBuild::default()
+                    .host(host)
+                    .port(port)
+                    .password(pass)
+                    .username(username)
+                    .database(database)
+                    .build()
+                    .await,
+            )
+        }
+        TypeMetadataStore::Memory => Arc::new(MemoryStorage::default()),
+        TypeMetadataStore::Sqlite => Arc::new(SqliteStorage::new(&md_sqlite_path).await?),
+    };
+    let snapshot = match &snapshot_path {
+        Some(path) => Snapshot::load(path, state.path()).await,
+        None => None,
+    };
+    state.build(ls.as_ref(), snapshot, &progress).await;
+
+    if let Some(path) = snapshot_path {
+        snapshot::schedule(state.clone(), path, Duration::from_secs(snapshot_interval));
+    }
+
+    let path = state.path().to_path_buf();
+
+    let change_log = if change_log_persist {
+        ls.init_change_log(change_log_size).await;
+        ChangeLog::load(ls.as_ref(), change_log_size).await
+    } else {
+        ChangeLog::new(change_log_size)
+    };
+    let change_log = Arc::new(RwLock::new(change_log));
+
+    let auth =
+        ConnectionAuthMS::new(grpc_auth_server, Duration::from_secs(permission_cache_ttl)).await;
+    auth.watch();
+
+    grpc_v1_server::BucketGrpcSrv::new(state.clone(), path.clone(), auth.clone())
+        .run(grpc_endpoint);
 
-    let manager = Manager::new(state.clone(), EventWatcher::new(path), ls)
-        .await
-        .start();
+    let bucket_sync = BucketSync::new(state.clone(), auth.clone(), path.clone()).start();
 
-    let state = Arc::new(State::new(state, manager).await);
+    let mut watcher = EventWatcher::new(path);
+    watcher.set_backend(match type_watcher {
+        TypeWatcher::Event => Backend::Event,
+        TypeWatcher::Poll => Backend::Poll(Duration::from_millis(watcher_interval)),
+    });
+    watcher.set_settle_window(Duration::from_millis(settle_window));
+
+    let manager = Manager::new(
+        state.clone(),
+        watcher,
+        ls,
+        change_log.clone(),
+        change_log_persist,
+        bucket_sync,
+    )
+    .await
+    .start();
+
+    let ws_config = WebSocketConfig {
+        buffer: ws_buffer,
+        policy: match ws_delivery_policy {
+            TypeDeliveryPolicy::DropOldest => DeliveryPolicy::DropOldest,
+            TypeDeliveryPolicy::DropNewest => DeliveryPolicy::DropNewest,
+            TypeDeliveryPolicy::Disconnect => DeliveryPolicy::Disconnect {
+                max_missed: ws_max_missed,
+            },
+        },
+        ping_interval: Duration::from_secs(ws_ping_interval),
+        pong_timeout: Duration::from_secs(ws_pong_timeout),
+        idle_timeout: Duration::from_secs(ws_idle_timeout),
+    };
+
+    if reconcile_interval > 0 {
+        reconcile::schedule(
+            manager.clone(),
+            Duration::from_secs(reconcile_interval),
+            reconcile_fix,
+        );
+    }
+
+    let state = Arc::new(
+        State::new(
+            state,
+            manager,
+            change_log,
+            auth,
+            ws_config,
+            progress.clone(),
+        )
+        .await,
+    );
 
     let cors = CorsBuilder::default()
         .allow_origin("http://localhost:8080")
         .allow_method(Method::PUT)
+        .allow_method(Method::POST)
         .allow_method(Method::GET)
         .allow_method(Method::OPTIONS)
         .allow_method(Method::PATCH)
@@ -139,6 +270,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .layer(trace),
     );
 
+    progress.set_ready();
+    let listener = booting.await?;
     tracing::info!("Listen: {listen}:{port}");
 
     loop {
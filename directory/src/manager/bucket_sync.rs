@@ -0,0 +1,291 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+use crate::{
+    actor::{Actor, ActorRef, Context, Envelope, Handler},
+    bucket::bucket_map::BucketMap,
+    grpc_v1::{BucketEvent, BucketOperation, ConnectionAuthMS},
+    manager::Change,
+};
+
+pub struct BucketSync {
+    tree: Arc<BucketMap>,
+    auth: ConnectionAuthMS,
+    root: PathBuf,
+}
+
+impl BucketSync {
+    pub fn new(tree: Arc<BucketMap>, auth: ConnectionAuthMS, root: PathBuf) -> Self {
+        Self { tree, auth, root }
+    }
+
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            report(Conflict::InvalidName(name.to_string()));
+            return None;
+        }
+        Some(self.root.join(name))
+    }
+
+    async fn create(&self, name: &str) {
+        let Some(path) = self.path(name) else {
+            return;
+        };
+
+        match tokio::fs::metadata(&path).await {
+            Ok(md) if md.is_dir() => {
+                tracing::debug!("[ BucketSync ] Bucket {name} already exists");
+            }
+            Ok(_) => report(Conflict::NotADirectory(name.to_string())),
+            Err(_) => match tokio::fs::create_dir(&path).await {
+                Ok(_) => tracing::info!("[ BucketSync ] Bucket {name} created"),
+                Err(er) => tracing::error!("[ BucketSync ] Create {path:?} error: {er}"),
+            },
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) {
+        let (Some(path_from), Some(path_to)) = (self.path(from), self.path(to)) else {
+            return;
+        };
+
+        let source = is_dir(&path_from).await;
+        let target = tokio::fs::try_exists(&path_to).await.unwrap_or_default();
+
+        match (source, target) {
+            (true, false) => match tokio::fs::rename(&path_from, &path_to).await {
+                Ok(_) => tracing::info!("[ BucketSync ] Bucket {from} renamed to {to}"),
+                Err(er) => tracing::error!("[ BucketSync ] Rename {from} to {to} error: {er}"),
+            },
+            (false, true) => {
+                tracing::debug!("[ BucketSync ] Bucket {from} already renamed to {to}");
+            }
+            (true, true) => report(Conflict::RenameTargetExists {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            (false, false) => report(Conflict::RenameSourceMissing {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+        }
+    }
+
+    async fn delete(&self, name: &str) {
+        let Some(path) = self.path(name) else {
+            return;
+        };
+
+        if !is_dir(&path).await {
+            tracing::debug!("[ BucketSync ] Bucket {name} doesn't exist");
+            return;
+        }
+
+        match tokio::fs::remove_dir_all(&path).await {
+            Ok(_) => tracing::info!("[ BucketSync ] Bucket {name} deleted"),
+            Err(er) => tracing::error!("[ BucketSync ] Delete {path:?} error: {er}"),
+        }
+    }
+
+    async fn register(&self, name: &str) {
+        match self.auth.register_bucket(name).await {
+            Ok(true) => tracing::info!("[ BucketSync ] Bucket {name} registered"),
+            Ok(false) => tracing::debug!("[ BucketSync ] Bucket {name} already registered"),
+            Err(er) => tracing::error!("[ BucketSync ] Register {name} error: {er}"),
+        }
+    }
+
+    async fn resync(&self) {
+        let Some(remote) = self.auth.registered_buckets().await else {
+            tracing::error!("[ BucketSync ] Resync error: buckets unavailable");
+            return;
+        };
+
+        let local = self
+            .tree
+            .get_buckets()
+            .into_iter()
+            .map(|x| x.name().to_string())
+            .collect::<Vec<_>>();
+
+        tracing::info!(
+            "[ BucketSync ] Resync {} remote and {} local buckets",
+            remote.buckets.len(),
+            local.len()
+        );
+
+        for name in remote.buckets.iter().filter(|x| !local.contains(x)) {
+            self.create(name).await;
+        }
+
+        for name in local.iter().filter(|x| !remote.buckets.contains(x)) {
+            report(Conflict::NotRegistered(name.to_string()));
+        }
+    }
+
+    async fn on_remote(&self, ev: BucketEvent) {
+        tracing::debug!("[ BucketSync ] Remote event {ev:?}");
+        match (ev.operation(), ev.old_bucket.as_deref()) {
+            (BucketOperation::New, _) => self.create(&ev.bucket).await,
+            (BucketOperation::Rename, Some(from)) => self.rename(from, &ev.bucket).await,
+            (BucketOperation::Rename, None) => {
+                tracing::error!("[ BucketSync ] Rename {} without source", ev.bucket);
+            }
+            (BucketOperation::Delete, _) => self.delete(&ev.bucket).await,
+            (BucketOperation::Resync, _) => self.resync().await,
+        }
+    }
+
+    async fn on_local(&self, change: Change) {
+        match change {
+            Change::NewBucket { bucket } => self.register(bucket.name()).await,
+            Change::NameBucket { from, to }
+                if self.auth.bucket_exists(to.name()).await == Some(false) =>
+            {
+                report(Conflict::RenamedOnDisk {
+                    from: from.name().to_string(),
+                    to: to.name().to_string(),
+                });
+            }
+            Change::DeleteBucket { bucket }
+                if self.auth.bucket_exists(bucket.name()).await == Some(true) =>
+            {
+                report(Conflict::DeletedOnDisk(bucket.name().to_string()));
+            }
+            Change::MoveKey { from, to } => {
+                if to.key.is_root() && !from.key.is_root() {
+                    self.register(to.bucket.name()).await;
+                } else if from.key.is_root()
+                    && !to.key.is_root()
+                    && self.auth.bucket_exists(from.bucket.name()).await == Some(true)
+                {
+                    report(Conflict::DeletedOnDisk(from.bucket.name().to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn is_dir(path: &Path) -> bool {
+    tokio::fs::metadata(path).await.is_ok_and(|x| x.is_dir())
+}
+
+fn report(conflict: Conflict) {
+    tracing::warn!("[ BucketSync ] Conflict: {conflict}");
+}
+
+#[derive(Debug)]
+pub enum Conflict {
+    InvalidName(String),
+    NotADirectory(String),
+    RenameSourceMissing { from: String, to: String },
+    RenameTargetExists { from: String, to: String },
+    RenamedOnDisk { from: String, to: String },
+    DeletedOnDisk(String),
+    NotRegistered(String),
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "{name:?} isn't a valid bucket name"),
+            Self::NotADirectory(name) => {
+                write!(f, "bucket {name} exists on disk but isn't a directory")
+            }
+            Self::RenameSourceMissing { from, to } => {
+                write!(
+                    f,
+                    "can't rename {from} to {to}, {from} doesn't exist on disk"
+                )
+            }
+            Self::RenameTargetExists { from, to } => {
+                write!(f, "can't rename {from} to {to}, both exist on disk")
+            }
+            Self::RenamedOnDisk { from, to } => {
+                write!(f, "bucket {from} renamed to {to} on disk but not in auth")
+            }
+            Self::DeletedOnDisk(name) => {
+                write!(
+                    f,
+                    "bucket {name} deleted on disk but still registered in auth"
+                )
+            }
+            Self::NotRegistered(name) => {
+                write!(
+                    f,
+                    "bucket {name} exists on disk but isn't registered in auth"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BucketSyncMessage {
+    Remote(BucketEvent),
+    Local(Box<Change>),
+    Resync,
+}
+
+impl Actor for BucketSync {
+    type Message = BucketSyncMessage;
+    type Reply = ();
+    type Context = Context<Self>;
+    type ActorRef = ActorRef<UnboundedSender<Envelope<Self>>, Self>;
+
+    fn start(mut self) -> Self::ActorRef {
+        let (tx, mut rx) = unbounded_channel();
+        let actor_ref = ActorRef::new(tx);
+        let mut ctx = Context::new(actor_ref.clone());
+
+        let auth = self.auth.clone();
+        let remote = actor_ref.clone();
+        tokio::spawn(async move {
+            loop {
+                match auth.watch_buckets().await {
+                    Ok(mut stream) => {
+                        tracing::info!("[ BucketSync ] Watching bucket changes");
+                        remote.tell(BucketSyncMessage::Resync).await;
+                        loop {
+                            match stream.message().await {
+                                Ok(Some(ev)) => remote.tell(BucketSyncMessage::Remote(ev)).await,
+                                Ok(None) => break,
+                                Err(er) => {
+                                    tracing::error!("[ BucketSync ] watch error: {er}");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(er) => tracing::error!("[ BucketSync ] watch error: {er}"),
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+
+        tokio::spawn(async move {
+            tracing::info!("[ BucketSync Init ]");
+            while let Some(Envelope { message, .. }) = rx.recv().await {
+                self.handle(message, &mut ctx).await;
+            }
+        });
+
+        actor_ref
+    }
+}
+
+impl Handler for BucketSync {
+    async fn handle(&mut self, message: Self::Message, _ctx: &mut Self::Context) -> Self::Reply {
+        match message {
+            BucketSyncMessage::Remote(ev) => self.on_remote(ev).await,
+            BucketSyncMessage::Local(change) => self.on_local(*change).await,
+            BucketSyncMessage::Resync => self.resync().await,
+        }
+    }
+}
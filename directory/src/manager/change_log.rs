@@ -0,0 +1,110 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use hyper_tungstenite::tungstenite;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bucket::{Bucket, key::Key},
+    manager::Change,
+    state::local_storage::MetadataStore,
+};
+
+#[derive(Debug, Serialize)]
+struct Sequenced<'a> {
+    seq: u64,
+    #[serde(flatten)]
+    change: &'a Change,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    pub seq: u64,
+    pub bucket: Option<Bucket<'static>>,
+    pub key: Option<Key<'static>>,
+    pub payload: String,
+    #[serde(default)]
+    pub resync: bool,
+    #[serde(default)]
+    pub destination: Option<(Option<Bucket<'static>>, Option<Key<'static>>)>,
+}
+
+impl ChangeRecord {
+    pub fn message(&self) -> tungstenite::Message {
+        tungstenite::Message::text(self.payload.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct ChangeLog {
+    records: VecDeque<Arc<ChangeRecord>>,
+    capacity: usize,
+    next: u64,
+}
+
+impl ChangeLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            next: 1,
+        }
+    }
+
+    pub async fn load(ls: &dyn MetadataStore, capacity: usize) -> Self {
+        let mut log = Self::new(capacity);
+        match ls.last_changes(log.capacity).await {
+            Ok(records) => {
+                log.next = records.last().map(|x| x.seq + 1).unwrap_or(1);
+                log.records = records.into_iter().map(Arc::new).collect();
+                tracing::info!(
+                    "[ ChangeLog ] {} changes restored, next sequence {}",
+                    log.records.len(),
+                    log.next
+                );
+            }
+            Err(er) => tracing::error!("[ ChangeLog ] restore error: {er}"),
+        }
+        log
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.next - 1
+    }
+
+    pub fn push(&mut self, change: &Change) -> Result<Arc<ChangeRecord>, serde_json::Error> {
+        let seq = self.next;
+        let payload = serde_json::to_string(&Sequenced { seq, change })?;
+        let (bucket, key) = change.location();
+        let record = Arc::new(ChangeRecord {
+            seq,
+            bucket: bucket.cloned(),
+            key: key.cloned(),
+            payload,
+            resync: matches!(change, Change::Resync { .. }),
+            destination: change.destination(),
+        });
+
+        self.next += 1;
+        if self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record.clone());
+
+        Ok(record)
+    }
+
+    pub fn since(&self, seq: u64) -> Option<Vec<Arc<ChangeRecord>>> {
+        let oldest = self.records.front().map(|x| x.seq).unwrap_or(self.next);
+        if seq >= self.next || seq + 1 < oldest {
+            return None;
+        }
+
+        Some(
+            self.records
+                .iter()
+                .filter(|x| x.seq > seq)
+                .cloned()
+                .collect(),
+        )
+    }
+}
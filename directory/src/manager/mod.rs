@@ -1,3 +1,7 @@
+pub mod bucket_sync;
+pub mod change_log;
+pub mod reconcile;
+pub mod rescan;
 pub mod utils;
 pub mod watcher;
 pub mod websocket;
@@ -8,41 +12,235 @@ use tokio::sync::{
     RwLock,
     mpsc::{UnboundedSender, unbounded_channel},
 };
+use uuid::Uuid;
 
 use crate::{
-    actor::{Actor, ActorRef, Context, Envelope, Handler},
+    actor::{Actor, ActorContext, ActorRef, Context, Envelope, Handler},
     bucket::{
         Bucket, Cowed,
         bucket_map::BucketMap,
         key::{Key, Segment},
         object::Object,
     },
-    manager::{utils::change_local_storage, watcher::event_watcher::EventWatcher},
-    state::local_storage::LocalStorage,
+    manager::{
+        bucket_sync::{BucketSync, BucketSyncMessage},
+        change_log::{ChangeLog, ChangeRecord},
+        reconcile::{Job, Jobs},
+        utils::change_local_storage,
+        watcher::event_watcher::EventWatcher,
+        websocket::{
+            broker::{WSBroker, WSBrokerMessage},
+            protocol::CloseReason,
+        },
+    },
+    state::local_storage::MetadataStore,
 };
 
 pub struct Manager {
-    state: Arc<RwLock<BucketMap>>,
+    state: Arc<BucketMap>,
     ref_watcher: Option<<EventWatcher as Actor>::ActorRef>,
     watcher: EventWatcher,
-    local_storage: Arc<LocalStorage>,
+    local_storage: Arc<dyn MetadataStore>,
+    change_log: Arc<RwLock<ChangeLog>>,
+    persist_changes: bool,
+    bucket_sync: <BucketSync as Actor>::ActorRef,
+    jobs: Arc<Jobs>,
 }
 
 impl Manager {
     pub async fn new(
-        state: Arc<RwLock<BucketMap>>,
+        state: Arc<BucketMap>,
         watcher: EventWatcher,
-        local_storage: Arc<LocalStorage>,
+        local_storage: Arc<dyn MetadataStore>,
+        change_log: Arc<RwLock<ChangeLog>>,
+        persist_changes: bool,
+        bucket_sync: <BucketSync as Actor>::ActorRef,
     ) -> Self {
         Self {
             state,
             ref_watcher: None,
             watcher,
             local_storage,
+            change_log,
+            persist_changes,
+            bucket_sync,
+            jobs: Arc::default(),
         }
     }
 }
 
+impl Manager {
+    async fn publish(brokers: Vec<<WSBroker as Actor>::ActorRef>, record: Arc<ChangeRecord>) {
+        tracing::debug!(
+            "[ Manager ] Publish change {} to {} brokers",
+            record.seq,
+            brokers.len()
+        );
+        for broker in brokers {
+            broker.tell(WSBrokerMessage::Message(record.clone())).await;
+        }
+    }
+
+    async fn apply(&mut self, mut change: Change) {
+        tracing::info!("[Scheduler]: New change: {change:?}");
+        change_local_storage(&mut change, self.local_storage.clone()).await;
+        let record = self.change_log.write().await.push(&change);
+        if let Ok(record) = &record
+            && self.persist_changes
+            && let Err(er) = self.local_storage.push_change(record).await
+        {
+            tracing::error!("[ Manager ] Persist change {} error: {er}", record.seq);
+        }
+
+        let sync = match &change {
+            Change::NewBucket { .. } | Change::NameBucket { .. } | Change::DeleteBucket { .. } => {
+                Some(change.clone())
+            }
+            Change::MoveKey { from, to } if from.key.is_root() || to.key.is_root() => {
+                Some(change.clone())
+            }
+            _ => None,
+        };
+        let destination = change.destination().map(|to| {
+            (
+                shared_brokers(change.location(), (to.0.as_ref(), to.1.as_ref())),
+                to,
+            )
+        });
+
+        let (brokers, deleted) = {
+            let tree = &self.state;
+            let (bucket, key) = change.location();
+            let mut brokers = tree.brokers(bucket, key).await;
+            let deleted = match &change {
+                Change::DeleteKey { bucket, key } => tree.subtree_brokers(bucket, Some(key)).await,
+                Change::DeleteBucket { bucket } => tree.subtree_brokers(bucket, None).await,
+                Change::Resync {
+                    bucket: Some(bucket),
+                    key,
+                } => {
+                    brokers.extend(
+                        tree.subtree_brokers(bucket, key.as_ref())
+                            .await
+                            .into_iter()
+                            .skip(1),
+                    );
+                    Vec::new()
+                }
+                Change::Resync { bucket: None, .. } => {
+                    for bucket in tree.get_buckets() {
+                        brokers.extend(tree.subtree_brokers(&bucket, None).await);
+                    }
+                    Vec::new()
+                }
+                _ => Vec::new(),
+            };
+            tree.change(change).await;
+            if let Some((shared, (bucket, key))) = &destination {
+                brokers.extend(
+                    tree.brokers(bucket.as_ref(), key.as_ref())
+                        .await
+                        .into_iter()
+                        .skip(*shared),
+                );
+            }
+            (brokers, deleted)
+        };
+
+        match record {
+            Ok(record) => Self::publish(brokers, record).await,
+            Err(er) => tracing::error!("[ Manager ] Change serialize error: {er}"),
+        }
+
+        for broker in deleted {
+            broker
+                .tell(WSBrokerMessage::Close(CloseReason::KeyDeleted))
+                .await;
+        }
+
+        if let Some(change) = sync {
+            self.bucket_sync
+                .tell(BucketSyncMessage::Local(Box::new(change)))
+                .await;
+        }
+    }
+
+    async fn rescan(&mut self, path: Option<PathBuf>, manager: <Manager as Actor>::ActorRef) {
+        let scope = rescan::scope(&self.state, path).await;
+        let stored = rescan::capture(&self.state, &scope).await;
+        let root = self.state.path().to_path_buf();
+
+        tokio::spawn(async move {
+            let changes = rescan::diff(&root, &scope, &stored).await;
+
+            tracing::warn!(
+                "[ Manager ] Rescan {:?}/{:?}: {} changes to converge",
+                scope.0,
+                scope.1,
+                changes.len()
+            );
+
+            for change in changes {
+                manager.tell(ManagerMessage::Change(Box::new(change))).await;
+            }
+
+            let (bucket, key) = scope;
+            manager
+                .tell(ManagerMessage::Change(Box::new(Change::Resync {
+                    bucket,
+                    key,
+                })))
+                .await;
+        });
+    }
+
+    async fn reconcile(&mut self, fix: bool, manager: <Manager as Actor>::ActorRef) -> Uuid {
+        let id = match self.jobs.start(fix) {
+            Ok(id) => id,
+            Err(running) => {
+                tracing::info!("[ Manager ] Reconcile {running} already running");
+                return running;
+            }
+        };
+
+        let snapshot = rescan::capture(&self.state, &(None, None)).await;
+        let root = self.state.path().to_path_buf();
+        let verify = self.state.verify_checksums();
+        let local_storage = self.local_storage.clone();
+        let jobs = self.jobs.clone();
+
+        tokio::spawn(async move {
+            let (mut report, changes) =
+                reconcile::run(&root, &snapshot, verify, local_storage.as_ref()).await;
+
+            tracing::info!(
+                "[ Manager ] Reconcile {id} found {} discrepancies",
+                report.discrepancies.len()
+            );
+            for discrepancy in &report.discrepancies {
+                tracing::warn!("[ Manager ] Reconcile {discrepancy:?}");
+            }
+
+            if fix && !changes.is_empty() {
+                report.fixed = changes.len();
+                for change in changes {
+                    manager.tell(ManagerMessage::Change(Box::new(change))).await;
+                }
+                manager
+                    .tell(ManagerMessage::Change(Box::new(Change::Resync {
+                        bucket: None,
+                        key: None,
+                    })))
+                    .await;
+            }
+
+            jobs.finish(id, report);
+        });
+
+        id
+    }
+}
+
 impl Actor for Manager {
     type Message = ManagerMessage;
     type Reply = ManagerReply;
@@ -82,17 +280,24 @@ impl Actor for Manager {
 }
 
 impl Handler for Manager {
-    async fn handle(&mut self, message: Self::Message, _ctx: &mut Self::Context) -> Self::Reply {
+    async fn handle(&mut self, message: Self::Message, ctx: &mut Self::Context) -> Self::Reply {
         match message {
-            ManagerMessage::Change(mut change) => {
-                tracing::info!("[Scheduler]: New change: {change:?}");
-                change_local_storage(&mut change, self.local_storage.clone()).await;
-                self.state.write().await.change(change.clone()).await;
-                //self.ws(MsgWs::Change(message.clone())).await.unwrap();
+            ManagerMessage::Change(change) => {
+                self.apply(*change).await;
                 ManagerReply::None
             }
+            ManagerMessage::Rescan(path) => {
+                self.rescan(path, ctx.actor_ref().clone()).await;
+                ManagerReply::None
+            }
+            ManagerMessage::Reconcile { fix } => {
+                ManagerReply::Reconciling(self.reconcile(fix, ctx.actor_ref().clone()).await)
+            }
+            ManagerMessage::Ask(ManagerAsk::Reconcile(id)) => {
+                ManagerReply::Reconciled(self.jobs.get(id))
+            }
             ManagerMessage::Ask(ManagerAsk::WhatIs(path)) => {
-                let tree = self.state.read().await;
+                let tree = &self.state;
                 let root = tree.path();
                 if path.parent().is_some_and(|x| x == root) {
                     ManagerReply::IsDir
@@ -101,7 +306,7 @@ impl Handler for Manager {
 
                     let key = Key::from_bucket(bucket.borrow(), &path).unwrap();
 
-                    if tree.get_entry(&bucket, &key).is_some() {
+                    if tree.contains(&bucket, &key).await {
                         ManagerReply::IsDir
                     } else {
                         ManagerReply::IsFile
@@ -120,6 +325,16 @@ pub enum Change {
         key: Key<'static>,
         object: Object,
     },
+    UpdateObject {
+        bucket: Bucket<'static>,
+        key: Key<'static>,
+        object: Object,
+    },
+    PendingObject {
+        bucket: Bucket<'static>,
+        key: Key<'static>,
+        file_name: String,
+    },
     NewKey {
         bucket: Bucket<'static>,
         key: Key<'static>,
@@ -154,19 +369,117 @@ pub enum Change {
     DeleteBucket {
         bucket: Bucket<'static>,
     },
+    MoveObject {
+        from: Box<ObjectLocation>,
+        to: Box<ObjectLocation>,
+    },
+    MoveKey {
+        from: Box<KeyLocation>,
+        to: Box<KeyLocation>,
+    },
+    Resync {
+        bucket: Option<Bucket<'static>>,
+        key: Option<Key<'static>>,
+    },
+}
+
+impl Change {
+    pub fn location(&self) -> (Option<&Bucket<'static>>, Option<&Key<'static>>) {
+        match self {
+            Self::NewObject { bucket, key, .. }
+            | Self::UpdateObject { bucket, key, .. }
+            | Self::PendingObject { bucket, key, .. }
+            | Self::NameObject { bucket, key, .. }
+            | Self::DeleteObject { bucket, key, .. }
+            | Self::NewKey { bucket, key }
+            | Self::DeleteKey { bucket, key }
+            | Self::NameKey {
+                bucket, from: key, ..
+            } => (Some(bucket), Some(key)),
+            Self::NewBucket { bucket } | Self::DeleteBucket { bucket } => (Some(bucket), None),
+            Self::NameBucket { from, .. } => (Some(from), None),
+            Self::Resync { bucket, key } => (bucket.as_ref(), key.as_ref()),
+            Self::MoveObject { from, .. } => (Some(&from.bucket), Some(&from.key)),
+            Self::MoveKey { from, .. } => (Some(&from.bucket), Some(&from.key)),
+        }
+    }
+
+    pub fn destination(&self) -> Option<(Option<Bucket<'static>>, Option<Key<'static>>)> {
+        match self {
+            Self::MoveObject { to, .. } => Some((Some(to.bucket.cloned()), Some(to.key.cloned()))),
+            Self::MoveKey { to, .. } if to.key.is_root() => Some((None, None)),
+            Self::MoveKey { to, .. } => Some((Some(to.bucket.cloned()), Some(to.parent()))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectLocation {
+    pub bucket: Bucket<'static>,
+    pub key: Key<'static>,
+    pub file_name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyLocation {
+    pub bucket: Bucket<'static>,
+    pub key: Key<'static>,
+}
+
+impl KeyLocation {
+    pub fn parent(&self) -> Key<'static> {
+        self.key
+            .name()
+            .rsplit_once('/')
+            .map(|(parent, _)| Key::new(parent.to_string()))
+            .unwrap_or(Key::root())
+    }
+}
+
+fn shared_brokers(
+    (bucket, key): (Option<&Bucket<'_>>, Option<&Key<'_>>),
+    (other_bucket, other_key): (Option<&Bucket<'_>>, Option<&Key<'_>>),
+) -> usize {
+    match (bucket, other_bucket) {
+        (Some(x), Some(y)) if x == y => {}
+        _ => return 1,
+    }
+
+    let segments = |key: Option<&Key<'_>>| {
+        key.filter(|x| !x.is_root())
+            .map(|x| {
+                x.name()
+                    .split('/')
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    2 + segments(key)
+        .iter()
+        .zip(segments(other_key).iter())
+        .take_while(|(x, y)| x == y)
+        .count()
 }
 
 pub enum ManagerMessage {
-    Change(Change),
+    Change(Box<Change>),
+    Rescan(Option<PathBuf>),
+    Reconcile { fix: bool },
     Ask(ManagerAsk),
 }
 
 pub enum ManagerAsk {
     WhatIs(PathBuf),
+    Reconcile(Uuid),
 }
 
 pub enum ManagerReply {
     None,
+    Reconciling(Uuid),
+    Reconciled(Option<Job>),
     IsDir,
     IsFile,
 }
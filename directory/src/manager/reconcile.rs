@@ -0,0 +1,377 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    actor::Actor,
+    bucket::{
+        Bucket, Cowed,
+        key::Key,
+        object::{CheckSum, Object, ObjectFingerprint, OwnerFile},
+    },
+    manager::{
+        Change, Manager, ManagerMessage,
+        rescan::{self, Stored},
+    },
+    state::local_storage::MetadataStore,
+};
+
+type Location = (Bucket<'static>, Key<'static>, String);
+
+const KEEP_JOBS: usize = 16;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Drift {
+    MissingInTree,
+    ExtraInTree,
+    OutdatedInTree,
+    MissingInDatabase,
+    ExtraInDatabase,
+    ChecksumMismatch,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Discrepancy {
+    pub drift: Drift,
+    pub bucket: Bucket<'static>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<Key<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Default, Clone)]
+pub struct Report {
+    pub discrepancies: Vec<Discrepancy>,
+    pub database: bool,
+    pub fixed: usize,
+}
+
+impl Report {
+    fn push(&mut self, drift: Drift, location: &Location) {
+        let (bucket, key, file_name) = location.clone();
+        self.discrepancies.push(Discrepancy {
+            drift,
+            bucket,
+            key: Some(key),
+            file_name: Some(file_name),
+        });
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Job {
+    Running { fix: bool },
+    Done { fix: bool, report: Report },
+}
+
+#[derive(Debug, Default)]
+pub struct Jobs {
+    inner: Mutex<VecDeque<(Uuid, Job)>>,
+}
+
+impl Jobs {
+    pub fn start(&self, fix: bool) -> Result<Uuid, Uuid> {
+        let mut jobs = self.inner.lock().unwrap();
+        if let Some((id, _)) = jobs
+            .iter()
+            .find(|(_, job)| matches!(job, Job::Running { .. }))
+        {
+            return Err(*id);
+        }
+
+        let id = Uuid::new_v4();
+        jobs.push_back((id, Job::Running { fix }));
+        while jobs.len() > KEEP_JOBS {
+            jobs.pop_front();
+        }
+        Ok(id)
+    }
+
+    pub fn finish(&self, id: Uuid, report: Report) {
+        if let Some((_, job)) = self
+            .inner
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|(x, _)| *x == id)
+        {
+            let fix = matches!(job, Job::Running { fix: true });
+            *job = Job::Done { fix, report };
+        }
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(x, _)| *x == id)
+            .map(|(_, job)| job.clone())
+    }
+}
+
+pub async fn run(
+    root: &Path,
+    snapshot: &BTreeMap<Bucket<'static>, Stored>,
+    verify: bool,
+    ls: &dyn MetadataStore,
+) -> (Report, Vec<Change>) {
+    let mut report = Report {
+        database: true,
+        ..Default::default()
+    };
+    let mut fixes = Vec::new();
+    let mut touched = BTreeSet::new();
+    let mut removed = Vec::new();
+
+    for change in rescan::diff(root, &(None, None), snapshot).await {
+        let (drift, file_name) = match &change {
+            Change::NewBucket { .. } | Change::NewKey { .. } => (Drift::MissingInTree, None),
+            Change::NewObject { object, .. } => (Drift::MissingInTree, Some(&object.file_name)),
+            Change::UpdateObject { object, .. } => (Drift::OutdatedInTree, Some(&object.file_name)),
+            Change::DeleteObject { file_name, .. } => (Drift::ExtraInTree, Some(file_name)),
+            Change::DeleteKey { bucket, key } => {
+                removed.push((bucket.cloned(), Some(key.cloned())));
+                (Drift::ExtraInTree, None)
+            }
+            Change::DeleteBucket { bucket } => {
+                removed.push((bucket.cloned(), None));
+                (Drift::ExtraInTree, None)
+            }
+            _ => continue,
+        };
+
+        let (bucket, key) = change.location();
+        let bucket = bucket.cloned().unwrap();
+        let key = key.cloned();
+        if let (Some(key), Some(file_name)) = (&key, file_name) {
+            touched.insert((bucket.cloned(), key.cloned(), file_name.clone()));
+        }
+        report.discrepancies.push(Discrepancy {
+            drift,
+            bucket,
+            key,
+            file_name: file_name.cloned(),
+        });
+        fixes.push(change);
+    }
+
+    let mut stored = BTreeMap::new();
+    let mut pending = BTreeSet::new();
+    for (bucket, entry) in snapshot {
+        inventory(bucket, &Key::root(), entry, &mut stored, &mut pending);
+    }
+
+    let mut on_disk = BTreeMap::new();
+    for (name, path) in rescan::list(root).await {
+        if path.is_dir() {
+            let bucket = Bucket::new_unchecked(name);
+            walk(&path, &bucket, &mut on_disk).await;
+        }
+    }
+
+    let mut by_key = BTreeMap::<_, Vec<_>>::new();
+    for ((bucket, key, file_name), path) in &on_disk {
+        by_key
+            .entry((bucket, key))
+            .or_default()
+            .push((file_name, path));
+    }
+
+    let mut in_database = BTreeSet::new();
+    for ((bucket, key), files) in by_key {
+        let names = files
+            .iter()
+            .map(|(file_name, _)| (*file_name).clone())
+            .collect::<Vec<_>>();
+        let database = match ls.get_objects(bucket.borrow(), key.borrow(), &names).await {
+            Ok(objects) => Some(
+                objects
+                    .into_iter()
+                    .map(|x| (x.file_name, x.checksum))
+                    .collect::<HashMap<_, _>>(),
+            ),
+            Err(er) => {
+                tracing::error!("[ Reconcile ] objects of {bucket}/{key} unavailable: {er}");
+                report.database = false;
+                None
+            }
+        };
+
+        for (file_name, path) in files {
+            let location = (bucket.cloned(), key.cloned(), file_name.clone());
+            let row = database.as_ref().and_then(|x| x.get(file_name));
+            if touched.contains(&location) {
+                if row.is_some() {
+                    in_database.insert(location);
+                }
+                continue;
+            }
+            if pending.contains(&location) {
+                continue;
+            }
+
+            let before = ObjectFingerprint::of(path);
+            let checksum = match stored.get(&location) {
+                Some(object) if !verify && object.unchanged(path) => object.checksum.clone(),
+                _ => match CheckSum::new(path.clone()).check_sum_async().await {
+                    Ok(checksum) => checksum,
+                    Err(er) => {
+                        tracing::error!("[ Reconcile ] checksum {path:?} error: {er}");
+                        continue;
+                    }
+                },
+            };
+
+            let drift = match (&database, row) {
+                (Some(_), None) => Some(Drift::MissingInDatabase),
+                (_, Some(x)) if *x != checksum => Some(Drift::ChecksumMismatch),
+                _ => stored
+                    .get(&location)
+                    .is_some_and(|x| x.checksum != checksum)
+                    .then_some(Drift::ChecksumMismatch),
+            };
+
+            if let Some(drift) = drift {
+                report.push(drift, &location);
+                let mut object = Object::with_checksum(path, OwnerFile::System, checksum, before);
+                if let Some(stored) = stored.get(&location) {
+                    object.preserve((*stored).clone());
+                }
+                let (bucket, key, _) = location;
+                fixes.push(Change::UpdateObject {
+                    bucket,
+                    key,
+                    object,
+                });
+            }
+        }
+    }
+
+    let mut extra = Vec::new();
+    if let Err(er) = ls
+        .visit_objects(&mut |x| {
+            let location = (x.bucket, x.key, x.file_name);
+            if !on_disk.contains_key(&location) && !pending.contains(&location) {
+                extra.push(location);
+            }
+        })
+        .await
+    {
+        tracing::error!("[ Reconcile ] objects collection unavailable: {er}");
+        report.database = false;
+    }
+
+    for location in extra {
+        report.push(Drift::ExtraInDatabase, &location);
+        let (bucket, key, file_name) = location;
+        if !removed
+            .iter()
+            .any(|(b, k)| covers(b, k.as_ref(), &bucket, &key))
+        {
+            fixes.push(Change::DeleteObject {
+                bucket,
+                key,
+                file_name,
+            });
+        }
+    }
+
+    let fixes = fixes
+        .into_iter()
+        .map(|change| match change {
+            Change::NewObject {
+                bucket,
+                key,
+                object,
+            } if in_database.contains(&(
+                bucket.cloned(),
+                key.cloned(),
+                object.file_name.clone(),
+            )) =>
+            {
+                Change::UpdateObject {
+                    bucket,
+                    key,
+                    object,
+                }
+            }
+            change => change,
+        })
+        .collect();
+
+    (report, fixes)
+}
+
+pub fn schedule(manager: <Manager as Actor>::ActorRef, every: Duration, fix: bool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            tracing::info!("[ Reconcile ] Scheduled run, fix: {fix}");
+            manager.tell(ManagerMessage::Reconcile { fix }).await;
+        }
+    });
+}
+
+fn inventory<'a>(
+    bucket: &Bucket<'static>,
+    key: &Key<'static>,
+    entry: &'a Stored,
+    stored: &mut BTreeMap<Location, &'a Object>,
+    pending: &mut BTreeSet<Location>,
+) {
+    for (file_name, object) in &entry.objects {
+        stored.insert((bucket.cloned(), key.cloned(), file_name.clone()), object);
+    }
+
+    for file_name in &entry.pending {
+        pending.insert((bucket.cloned(), key.cloned(), file_name.clone()));
+    }
+
+    for (seg, entry) in &entry.keys {
+        let key = key.child(seg);
+        inventory(bucket, &key, entry, stored, pending);
+    }
+}
+
+async fn walk(path: &Path, bucket: &Bucket<'static>, on_disk: &mut BTreeMap<Location, PathBuf>) {
+    let mut stack = vec![(path.to_path_buf(), Key::root())];
+    while let Some((path, key)) = stack.pop() {
+        for (name, path) in rescan::list(&path).await {
+            if path.is_dir() {
+                stack.push((path, key.child(&name)));
+            } else {
+                on_disk.insert((bucket.cloned(), key.cloned(), name), path);
+            }
+        }
+    }
+}
+
+fn covers(
+    bucket: &Bucket<'_>,
+    key: Option<&Key<'_>>,
+    other: &Bucket<'_>,
+    other_key: &Key<'_>,
+) -> bool {
+    if bucket != other {
+        return false;
+    }
+
+    key.is_none_or(|key| {
+        other_key
+            .name()
+            .strip_prefix(key.name())
+            .is_some_and(|x| x.is_empty() || x.starts_with('/'))
+    })
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+
+use futures::FutureExt;
+
+use crate::{
+    bucket::{
+        Bucket, Cowed,
+        bucket_map::{BucketMap, KeyEntry},
+        key::Key,
+        object::{Object, ObjectModified, OwnerFile, from_systemtime},
+        utils::Changed,
+    },
+    manager::Change,
+};
+
+pub type Scope = (Option<Bucket<'static>>, Option<Key<'static>>);
+
+#[derive(Debug, Default)]
+pub struct Stored {
+    pub objects: BTreeMap<String, Object>,
+    pub pending: BTreeSet<String>,
+    pub keys: BTreeMap<String, Stored>,
+}
+
+impl From<&KeyEntry> for Stored {
+    fn from(value: &KeyEntry) -> Self {
+        Self {
+            objects: value
+                .objects
+                .iter()
+                .flatten()
+                .map(|x| (x.file_name.clone(), x.clone()))
+                .collect(),
+            pending: value.pending.clone().unwrap_or_default(),
+            keys: value
+                .keys
+                .iter()
+                .flatten()
+                .map(|(seg, entry)| (seg.as_ref().to_string(), entry.into()))
+                .collect(),
+        }
+    }
+}
+
+pub async fn scope(tree: &BucketMap, path: Option<PathBuf>) -> Scope {
+    let root = tree.path();
+    let mut path = path.unwrap_or_else(|| root.to_path_buf());
+
+    while path != root {
+        if path.is_dir()
+            && let Some(bucket) = Bucket::find_bucket(root, &path)
+            && let Some(key) = Key::from_bucket(bucket.borrow(), &path)
+            && tree.contains(&bucket, &key).await
+        {
+            return (Some(bucket), Some(key));
+        }
+
+        match path.parent() {
+            Some(parent) => path = parent.to_path_buf(),
+            None => break,
+        }
+    }
+
+    (None, None)
+}
+
+pub async fn capture(tree: &BucketMap, (bucket, key): &Scope) -> BTreeMap<Bucket<'static>, Stored> {
+    let mut stored = BTreeMap::new();
+
+    match bucket {
+        Some(bucket) => {
+            if let Some(root) = tree.read_bucket(bucket).await
+                && let Some(entry) = root.get(key.as_ref().unwrap_or(&Key::root()))
+            {
+                stored.insert(bucket.cloned(), entry.into());
+            }
+        }
+        None => {
+            for (bucket, entry) in tree.buckets() {
+                let entry = Stored::from(&*entry.read().await);
+                stored.insert(bucket, entry);
+            }
+        }
+    }
+
+    stored
+}
+
+pub async fn diff(
+    root: &Path,
+    (bucket, key): &Scope,
+    stored: &BTreeMap<Bucket<'static>, Stored>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    match (bucket, key) {
+        (Some(bucket), key) => {
+            let key = key.clone().unwrap_or(Key::root());
+            if let Some(entry) = stored.get(bucket) {
+                let path = path_of(root, bucket, &key);
+                diff_entry(&path, bucket, &key, Some(entry), &mut changes).await;
+            }
+        }
+        (None, _) => {
+            let on_disk = list(root).await;
+
+            for (name, path) in on_disk.iter().filter(|(_, x)| x.is_dir()) {
+                let bucket = Bucket::new_unchecked(name.clone());
+                let entry = stored.get(&bucket);
+                if entry.is_none() {
+                    changes.push(Change::NewBucket {
+                        bucket: bucket.cloned(),
+                    });
+                }
+                diff_entry(path, &bucket, &Key::root(), entry, &mut changes).await;
+            }
+
+            for bucket in stored.keys() {
+                if !on_disk
+                    .iter()
+                    .any(|(name, path)| name == bucket.name() && path.is_dir())
+                {
+                    changes.push(Change::DeleteBucket {
+                        bucket: bucket.cloned(),
+                    });
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+fn diff_entry<'a>(
+    path: &'a Path,
+    bucket: &'a Bucket<'static>,
+    key: &'a Key<'static>,
+    entry: Option<&'a Stored>,
+    changes: &'a mut Vec<Change>,
+) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+    async move {
+        let on_disk = list(path).await;
+        let mut files = BTreeSet::new();
+        let mut dirs = BTreeSet::new();
+
+        for (name, path) in &on_disk {
+            let Ok(md) = tokio::fs::metadata(path).await else {
+                continue;
+            };
+
+            if md.is_dir() {
+                dirs.insert(name.as_str());
+                let child_key = key.child(name);
+                let child_entry = entry.and_then(|x| x.keys.get(name));
+
+                if child_entry.is_none() {
+                    changes.push(Change::NewKey {
+                        bucket: bucket.cloned(),
+                        key: child_key.cloned(),
+                    });
+                }
+                diff_entry(path, bucket, &child_key, child_entry, changes).await;
+                continue;
+            }
+
+            files.insert(name.as_str());
+            if entry.is_some_and(|x| x.pending.contains(name)) {
+                continue;
+            }
+
+            let stored = entry.and_then(|x| x.objects.get(name));
+
+            match stored {
+                None => changes.push(Change::NewObject {
+                    bucket: bucket.cloned(),
+                    key: key.cloned(),
+                    object: Object::new(path, OwnerFile::default()).await,
+                }),
+                Some(stored) => {
+                    let modified = ObjectModified::from(md.modified().map(from_systemtime).ok());
+                    if stored.size != md.len() as i64 || modified.change(&stored.modified) {
+                        changes.push(Change::UpdateObject {
+                            bucket: bucket.cloned(),
+                            key: key.cloned(),
+                            object: Object::new(path, OwnerFile::System).await,
+                        });
+                    }
+                }
+            }
+        }
+
+        let Some(entry) = entry else {
+            return;
+        };
+
+        for file_name in entry.objects.keys() {
+            if !files.contains(file_name.as_str()) {
+                changes.push(Change::DeleteObject {
+                    bucket: bucket.cloned(),
+                    key: key.cloned(),
+                    file_name: file_name.clone(),
+                });
+            }
+        }
+
+        for seg in entry.keys.keys() {
+            if !dirs.contains(seg.as_str()) {
+                changes.push(Change::DeleteKey {
+                    bucket: bucket.cloned(),
+                    key: key.child(seg),
+                });
+            }
+        }
+    }
+    .boxed()
+}
+
+pub async fn list(path: &Path) -> Vec<(String, PathBuf)> {
+    let mut resp = Vec::new();
+    let Ok(mut read_dir) = tokio::fs::read_dir(path).await else {
+        tracing::error!("[ Rescan ] {path:?} can't be read");
+        return resp;
+    };
+
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        match entry.file_name().into_string() {
+            Ok(name) => resp.push((name, entry.path())),
+            Err(name) => tracing::warn!("[ Rescan ] Skip invalid name {name:?}"),
+        }
+    }
+
+    resp
+}
+
+fn path_of(root: &Path, bucket: &Bucket<'_>, key: &Key<'_>) -> PathBuf {
+    let path = root.join(bucket.name());
+    if key.is_root() {
+        path
+    } else {
+        path.join(key.name())
+    }
+}
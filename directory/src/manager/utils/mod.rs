@@ -15,8 +15,11 @@ use crate::{
             normalizeds::{NormalizeFileUtf8, NormalizePathUtf8},
         },
     },
-    manager::{Change, utils::skipper::Skipper},
-    state::local_storage::LocalStorage,
+    manager::{
+        Change, KeyLocation, ObjectLocation, utils::skipper::Skipper,
+        watcher::event_watcher::Pending,
+    },
+    state::local_storage::MetadataStore,
 };
 
 pub async fn hd_new_bucket_or_key_watcher(
@@ -88,7 +91,7 @@ pub async fn hd_new_object_watcher(
     path: PathBuf,
     root: &Path,
     skip: Skipper,
-) -> Result<Change, ()> {
+) -> Result<(Change, PathBuf), ()> {
     if path.parent().is_some_and(|x| x == root) {
         tracing::error!("[Event Watcher] Objects aren't allowed in the root path");
         return Err(());
@@ -106,28 +109,32 @@ pub async fn hd_new_object_watcher(
 
             let bucket = Bucket::find_bucket(root, &path).unwrap();
             let key = Key::from_bucket(bucket.borrow(), &parent).unwrap();
-            let object = Object::new(&to_, OwnerFile::User("Test".to_string())).await;
             skip.object_tracker()
-                .to_skip(bucket.cloned(), key.cloned(), to)
+                .to_skip(bucket.cloned(), key.cloned(), to.clone())
                 .await;
 
-            Ok(Change::NewObject {
-                bucket,
-                key,
-                object,
-            })
+            Ok((
+                Change::PendingObject {
+                    bucket,
+                    key,
+                    file_name: to,
+                },
+                to_,
+            ))
         }
-        Ok(RenameDecision::Not(_)) => {
+        Ok(RenameDecision::Not(file_name)) => {
             let bucket = Bucket::find_bucket(root, &path).unwrap();
             let key = Key::from_bucket(bucket.borrow(), path.parent().unwrap()).unwrap();
-            let object = Object::new(&path, OwnerFile::User("test".to_string())).await;
-            tracing::trace!("[Event Watcher] bucket: {bucket} - key: {key} - object: {object:?}");
+            tracing::trace!("[Event Watcher] bucket: {bucket} - key: {key} - pending: {file_name}");
 
-            Ok(Change::NewObject {
-                bucket,
-                key,
-                object,
-            })
+            Ok((
+                Change::PendingObject {
+                    bucket,
+                    key,
+                    file_name,
+                },
+                path,
+            ))
         }
         Err(er) => {
             tracing::error!("[ fn hd_new_object_watcher ] NormalizeFIleUtf8 Error {er:?}");
@@ -137,6 +144,42 @@ pub async fn hd_new_object_watcher(
     }
 }
 
+pub async fn hd_settled_object_watcher(
+    path: PathBuf,
+    root: &Path,
+    pending: Pending,
+) -> Result<Change, ()> {
+    if path.parent().is_some_and(|x| x == root) || !path.is_file() {
+        return Err(());
+    }
+
+    let Some(bucket) = Bucket::find_bucket(root, &path) else {
+        tracing::error!("[ fn hd_settled_object_watcher ] bucket not found - path: {path:?}");
+        return Err(());
+    };
+    let Some(key) = Key::from_bucket(bucket.borrow(), path.parent().unwrap()) else {
+        tracing::error!(
+            "[ fn hd_settled_object_watcher ] key not found from the bucket {bucket:?} - path: {path:?}"
+        );
+        return Err(());
+    };
+
+    tracing::trace!("[ fn hd_settled_object_watcher ] bucket: {bucket} - key: {key} - {pending:?}");
+
+    Ok(match pending {
+        Pending::New => Change::NewObject {
+            bucket,
+            key,
+            object: Object::new(&path, OwnerFile::User("test".to_string())).await,
+        },
+        Pending::Update => Change::UpdateObject {
+            bucket,
+            key,
+            object: Object::new(&path, OwnerFile::System).await,
+        },
+    })
+}
+
 pub async fn hd_rename_path<'a>(
     root: &Path,
     original_from: PathBuf,
@@ -146,21 +189,14 @@ pub async fn hd_rename_path<'a>(
     match NormalizePathUtf8::default().run(&original_to) {
         Ok(RenameDecision::Not(name)) => {
             if original_to.parent().is_some_and(|x| x == root) {
-                let to = Bucket::new_unchecked(name);
+                let to = Bucket::new_unchecked(name.clone());
 
                 if skipped.bucket_tracker().skipped(&to).await {
                     tracing::trace!("[ fn hd_rename_parh ] skipped {to:?}");
                     return Err(());
                 }
-
-                let from = Bucket::new_unchecked(
-                    original_from.file_name().and_then(|x| x.to_str()).unwrap(),
-                )
-                .owned();
-                Ok(Change::NameBucket { from, to })
             } else {
                 let bucket = Bucket::find_bucket(root, &original_to).unwrap();
-                let key = Segment::new(name);
 
                 if skipped
                     .key_tracker()
@@ -170,17 +206,12 @@ pub async fn hd_rename_path<'a>(
                     )
                     .await
                 {
-                    tracing::trace!("[ fn hd_rename_part ] skipped {bucket:?} {key:?}");
+                    tracing::trace!("[ fn hd_rename_part ] skipped {bucket:?} {name:?}");
                     return Err(());
                 }
-
-                let key_from = Key::from_bucket(bucket.borrow(), &original_from).unwrap();
-                Ok(Change::NameKey {
-                    bucket,
-                    from: key_from,
-                    to: key,
-                })
             }
+
+            path_change(root, &original_from, &original_to, name)
         }
         Ok(RenameDecision::Yes(Rename { parent, from, to })) => {
             let from_ = parent.join(&from);
@@ -192,34 +223,20 @@ pub async fn hd_rename_path<'a>(
             }
 
             if parent == root {
-                let bucket = Bucket::new_unchecked(to);
+                let bucket = Bucket::new_unchecked(to.clone());
                 skipped.bucket_tracker().to_skip(bucket.cloned()).await;
-                let original_name = Bucket::new_unchecked(
-                    original_from.file_name().and_then(|x| x.to_str()).unwrap(),
-                )
-                .owned();
-                Ok(Change::NameBucket {
-                    from: original_name,
-                    to: bucket,
-                })
             } else {
                 let bucket = Bucket::find_bucket(root, &original_to).unwrap();
-                let original_key = Key::from_bucket(bucket.borrow(), &original_from).unwrap();
-                let key = Segment::new(to);
                 skipped
                     .key_tracker()
                     .to_skip(
                         bucket.cloned(),
-                        Key::from_bucket(bucket.borrow(), &original_to).unwrap(),
+                        Key::from_bucket(bucket.borrow(), &to_).unwrap(),
                     )
                     .await;
-
-                Ok(Change::NameKey {
-                    bucket,
-                    from: original_key,
-                    to: key,
-                })
             }
+
+            path_change(root, &original_from, &to_, to)
         }
         Ok(RenameDecision::NeedRestore) => {
             tracing::trace!(
@@ -252,18 +269,8 @@ pub async fn hd_rename_object<'a>(
                 );
                 return Err(());
             }
-            let old_name = original_from
-                .file_name()
-                .and_then(|x| x.to_str())
-                .unwrap()
-                .to_string();
 
-            Ok(Change::NameObject {
-                bucket,
-                key,
-                from: old_name,
-                to: name,
-            })
+            object_change(root, &original_from, bucket, key, name)
         }
         Ok(RenameDecision::Yes(Rename { parent, from, to })) => {
             let from_ = parent.join(&from);
@@ -277,12 +284,7 @@ pub async fn hd_rename_object<'a>(
                 .object_tracker()
                 .to_skip(bucket.clone(), key.clone(), to.clone())
                 .await;
-            Ok(Change::NameObject {
-                bucket,
-                key,
-                from,
-                to,
-            })
+            object_change(root, &original_from, bucket, key, to)
         }
         Err(er) => {
             tracing::error!("{er:?}");
@@ -295,7 +297,115 @@ pub async fn hd_rename_object<'a>(
     }
 }
 
-pub async fn change_local_storage(ch: &mut Change, ls: Arc<LocalStorage>) {
+fn object_change(
+    root: &Path,
+    original_from: &Path,
+    bucket: Bucket<'static>,
+    key: Key<'static>,
+    name: String,
+) -> Result<Change, ()> {
+    let location = || -> Option<ObjectLocation> {
+        let parent = original_from.parent().filter(|x| *x != root)?;
+        let bucket = Bucket::find_bucket(root, original_from)?;
+        let key = Key::from_bucket(bucket.borrow(), parent)?;
+        let file_name = original_from.file_name()?.to_str()?.to_string();
+        Some(ObjectLocation {
+            bucket,
+            key,
+            file_name,
+        })
+    };
+
+    let Some(ObjectLocation {
+        bucket: from_bucket,
+        key: from_key,
+        file_name: from,
+    }) = location()
+    else {
+        tracing::error!(
+            "[ fn hd_rename_object ] invalid rename from {original_from:?} to {bucket}/{key}/{name}"
+        );
+        return Err(());
+    };
+
+    Ok(if from_bucket == bucket && from_key == key {
+        Change::NameObject {
+            bucket,
+            key,
+            from,
+            to: name,
+        }
+    } else {
+        Change::MoveObject {
+            from: Box::new(ObjectLocation {
+                bucket: from_bucket,
+                key: from_key,
+                file_name: from,
+            }),
+            to: Box::new(ObjectLocation {
+                bucket,
+                key,
+                file_name: name,
+            }),
+        }
+    })
+}
+
+fn path_change(
+    root: &Path,
+    original_from: &Path,
+    original_to: &Path,
+    name: String,
+) -> Result<Change, ()> {
+    let location = |path: &Path, name: Option<String>| -> Option<KeyLocation> {
+        if path.parent().is_some_and(|x| x == root) {
+            let name = match name {
+                Some(name) => name,
+                None => path.file_name()?.to_str()?.to_string(),
+            };
+            return Some(KeyLocation {
+                bucket: Bucket::new_unchecked(name),
+                key: Key::root(),
+            });
+        }
+
+        let bucket = Bucket::find_bucket(root, path)?;
+        let key = Key::from_bucket(bucket.borrow(), path)?;
+        Some(KeyLocation { bucket, key })
+    };
+
+    let (Some(from), Some(to)) = (
+        location(original_from, None),
+        location(original_to, Some(name.clone())),
+    ) else {
+        tracing::error!(
+            "[ fn hd_rename_path ] invalid rename from {original_from:?} to {original_to:?}"
+        );
+        return Err(());
+    };
+
+    Ok(match (from.key.is_root(), to.key.is_root()) {
+        (true, true) => Change::NameBucket {
+            from: from.bucket,
+            to: to.bucket,
+        },
+        (false, false)
+            if from.bucket == to.bucket && original_from.parent() == original_to.parent() =>
+        {
+            Change::NameKey {
+                bucket: from.bucket,
+                from: from.key,
+                to: Segment::new(name),
+            }
+        }
+        _ => Change::MoveKey {
+            from: Box::new(from),
+            to: Box::new(to),
+        },
+    })
+}
+
+pub async fn change_local_storage(ch: &mut Change, ls: Arc<dyn MetadataStore>) {
     match ch {
         Change::NewObject {
             object,
@@ -306,6 +416,19 @@ pub async fn change_local_storage(ch: &mut Change, ls: Arc<LocalStorage>) {
                 tracing::error!("[ fn change_local_storage ] error: {er}");
             }
         }
+        Change::UpdateObject {
+            object,
+            key,
+            bucket,
+        } => match ls.sync_object(bucket.borrow(), key.borrow(), object).await {
+            Ok(true) => {}
+            Ok(false) => {
+                if let Err(er) = ls.new_object(bucket.borrow(), key.borrow(), object).await {
+                    tracing::error!("[ fn change_local_storage ] error: {er}");
+                }
+            }
+            Err(er) => tracing::error!("[ fn change_local_storage ] error: {er}"),
+        },
         Change::DeleteObject {
             file_name,
             bucket,
@@ -347,6 +470,17 @@ pub async fn change_local_storage(ch: &mut Change, ls: Arc<LocalStorage>) {
                 tracing::debug!("{er}")
             }
         }
+        Change::MoveObject { from, to } => {
+            if let Err(er) = ls.move_object(from, to).await {
+                tracing::error!("[ fn change_local_storage ] error: {er}");
+            }
+        }
+        Change::MoveKey { from, to } => {
+            if let Err(er) = ls.move_key(from, to).await {
+                tracing::error!("[ fn change_local_storage ] error: {er}");
+            }
+        }
+        Change::PendingObject { .. } | Change::Resync { .. } => {}
         e => tracing::warn!("[fn change_local_storage] Unimplemented arm; change: {e:?}"),
     }
 }
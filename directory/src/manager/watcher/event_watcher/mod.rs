@@ -1,11 +1,13 @@
 mod rename_control;
+mod settle_control;
 
 use notify::{
     INotifyWatcher, Watcher as _,
-    event::{CreateKind, Event, ModifyKind, RemoveKind, RenameMode},
+    event::{AccessKind, AccessMode, CreateKind, Event, ModifyKind, RemoveKind, RenameMode},
 };
 pub use rename_control::*;
-use std::path::PathBuf;
+pub use settle_control::*;
+use std::{path::PathBuf, time::Duration};
 use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
 
 use crate::{
@@ -15,17 +17,28 @@ use crate::{
         Change, Manager, ManagerAsk, ManagerMessage, ManagerReply,
         utils::{
             hd_new_bucket_or_key_watcher, hd_new_object_watcher, hd_rename_object, hd_rename_path,
-            skipper::Skipper,
+            hd_settled_object_watcher, skipper::Skipper,
         },
+        watcher::pool_watcher::PollWatcherNotify,
     },
 };
 
+#[derive(Debug, Clone, Copy)]
+pub enum Backend {
+    Event,
+    Poll(Duration),
+}
+
 pub struct EventWatcher {
+    backend: Backend,
     notify_watcher: Option<INotifyWatcher>,
+    ref_poll_watcher: Option<<PollWatcherNotify as Actor>::ActorRef>,
     r#await: u64,
     ref_manager: Option<<Manager as Actor>::ActorRef>,
     path: PathBuf,
     ref_rename_control: Option<<RenameControl as Actor>::ActorRef>,
+    settle_window: Duration,
+    ref_settle_control: Option<<SettleControl as Actor>::ActorRef>,
     skipper: Skipper,
 }
 
@@ -38,10 +51,14 @@ impl std::fmt::Debug for EventWatcher {
 impl EventWatcher {
     pub fn new(path: PathBuf) -> Self {
         Self {
+            backend: Backend::Event,
             notify_watcher: None,
+            ref_poll_watcher: None,
             r#await: 2000,
             ref_manager: None,
             ref_rename_control: None,
+            settle_window: Duration::from_millis(2000),
+            ref_settle_control: None,
             path,
             skipper: Skipper::default(),
         }
@@ -54,15 +71,31 @@ impl EventWatcher {
     pub fn set_rename_control_await(&mut self, r#await: u64) {
         self.r#await = r#await;
     }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn set_settle_window(&mut self, window: Duration) {
+        self.settle_window = window;
+    }
+
+    async fn settle(&self, message: Settle) -> Option<Pending> {
+        self.ref_settle_control.as_ref().unwrap().ask(message).await
+    }
 }
 
 impl std::clone::Clone for EventWatcher {
     fn clone(&self) -> Self {
         Self {
+            backend: self.backend,
             notify_watcher: None,
+            ref_poll_watcher: None,
             r#await: self.r#await,
             ref_manager: None,
             ref_rename_control: None,
+            settle_window: self.settle_window,
+            ref_settle_control: None,
             path: self.path.clone(),
             skipper: Skipper::default(),
         }
@@ -79,23 +112,36 @@ impl Actor for EventWatcher {
         let (tx, mut rx) = unbounded_channel();
         let tx_0 = tx.clone();
         let self_ref = ActorRef::new(tx);
-        let mut notify_w = notify::recommended_watcher(move |ev| match ev {
-            Ok(ev) => {
-                tracing::info!("[ New Event ]: {ev:?}");
-                tx_0.send(Envelope::tell(ev)).unwrap();
-            }
-            Err(er) => tracing::error!("[ Notify Error ]: {er}"),
-        })
-        .unwrap();
 
-        notify_w
-            .watch(&self.path, notify::RecursiveMode::Recursive)
-            .unwrap();
+        match self.backend {
+            Backend::Event => {
+                let mut notify_w = notify::recommended_watcher(move |ev| match ev {
+                    Ok(ev) => {
+                        tracing::info!("[ New Event ]: {ev:?}");
+                        tx_0.send(Envelope::tell(ev)).unwrap();
+                    }
+                    Err(er) => tracing::error!("[ Notify Error ]: {er}"),
+                })
+                .unwrap();
+
+                notify_w
+                    .watch(&self.path, notify::RecursiveMode::Recursive)
+                    .unwrap();
+
+                self.notify_watcher = Some(notify_w);
+            }
+            Backend::Poll(interval) => {
+                let poll_watcher =
+                    PollWatcherNotify::new(&self.path, interval, self_ref.clone()).unwrap();
+                self.ref_poll_watcher = Some(poll_watcher.start());
+            }
+        }
 
         let rename_control = RenameControl::new(self_ref.clone(), self.r#await);
         self.ref_rename_control = Some(rename_control.start());
 
-        self.notify_watcher = Some(notify_w);
+        let settle_control = SettleControl::new(self_ref.clone(), self.settle_window);
+        self.ref_settle_control = Some(settle_control.start());
 
         let mut ctx = Context::new(self_ref.clone());
 
@@ -120,6 +166,18 @@ impl Handler for EventWatcher {
     async fn handle(&mut self, message: Self::Message, _ctx: &mut Self::Context) -> Self::Reply {
         let root = &self.path;
         let event = message;
+
+        if event.need_rescan() {
+            let mut paths = event.paths;
+            tracing::warn!("[ EventWatcher ] Events lost, rescan {paths:?}");
+            self.ref_manager
+                .as_ref()
+                .unwrap()
+                .tell(ManagerMessage::Rescan(paths.pop()))
+                .await;
+            return ();
+        }
+
         match event.kind {
             notify::EventKind::Create(CreateKind::Folder) => {
                 let mut paths = event.paths;
@@ -134,7 +192,7 @@ impl Handler for EventWatcher {
                         self.ref_manager
                             .as_ref()
                             .unwrap()
-                            .tell(ManagerMessage::Change(ch))
+                            .tell(ManagerMessage::Change(Box::new(ch)))
                             .await;
                     }
                     Err(()) => {
@@ -155,12 +213,13 @@ impl Handler for EventWatcher {
                 };
 
                 match hd_new_object_watcher(path, root, self.skipper.clone()).await {
-                    Ok(ch) => {
+                    Ok((ch, path)) => {
                         self.ref_manager
                             .as_ref()
                             .unwrap()
-                            .tell(ManagerMessage::Change(ch))
+                            .tell(ManagerMessage::Change(Box::new(ch)))
                             .await;
+                        self.settle(Settle::Track(path, Pending::New)).await;
                     }
                     Err(()) => {
                         tracing::error!("[ CreateKinfOther ] Error")
@@ -190,16 +249,20 @@ impl Handler for EventWatcher {
                     return ();
                 };
 
-                self.ref_rename_control
-                    .as_ref()
-                    .unwrap()
-                    .tell(Rename::Decline(from.clone()))
-                    .await;
+                if let Backend::Event = self.backend {
+                    self.ref_rename_control
+                        .as_ref()
+                        .unwrap()
+                        .tell(Rename::Decline(from.clone()))
+                        .await;
+                }
+
+                let pending = self.settle(Settle::Cancel(from.clone())).await;
 
                 let ch = if to.is_dir() {
-                    hd_rename_path(root, from, to, self.skipper.clone()).await
+                    hd_rename_path(root, from, to.clone(), self.skipper.clone()).await
                 } else {
-                    hd_rename_object(root, from, to, self.skipper.clone()).await
+                    hd_rename_object(root, from, to.clone(), self.skipper.clone()).await
                 };
 
                 match ch {
@@ -207,14 +270,49 @@ impl Handler for EventWatcher {
                         self.ref_manager
                             .as_ref()
                             .unwrap()
-                            .tell(ManagerMessage::Change(ch))
+                            .tell(ManagerMessage::Change(Box::new(ch)))
                             .await;
+                        if let Some(pending) = pending {
+                            self.settle(Settle::Track(to, pending)).await;
+                        }
                     }
                     Err(()) => {
                         tracing::error!("[ ModifyKind::Rename ] Error")
                     }
                 }
             }
+            notify::EventKind::Modify(ModifyKind::Data(_)) => {
+                let mut path = event.paths;
+                let Some(path) = path.pop() else {
+                    return ();
+                };
+
+                tracing::debug!("[ EventWatcher ] {{ ModifyKind::Data }} {path:?}");
+
+                if path.is_file() {
+                    self.settle(Settle::Track(path, Pending::Update)).await;
+                }
+            }
+            notify::EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                let mut path = event.paths;
+                let Some(path) = path.pop() else {
+                    return ();
+                };
+
+                let Some(pending) = self.settle(Settle::Done(path.clone())).await else {
+                    return ();
+                };
+
+                tracing::debug!("[ EventWatcher ] {{ AccessKind::Close }} {path:?} {pending:?}");
+
+                if let Ok(ch) = hd_settled_object_watcher(path, root, pending).await {
+                    self.ref_manager
+                        .as_ref()
+                        .unwrap()
+                        .tell(ManagerMessage::Change(Box::new(ch)))
+                        .await;
+                }
+            }
             notify::EventKind::Remove(RemoveKind::Folder) => {
                 let mut path = event.paths;
                 let Some(path) = path.pop() else {
@@ -228,14 +326,19 @@ impl Handler for EventWatcher {
                     self.ref_manager
                         .as_ref()
                         .unwrap()
-                        .tell(ManagerMessage::Change(Change::DeleteBucket { bucket }))
+                        .tell(ManagerMessage::Change(Box::new(Change::DeleteBucket {
+                            bucket,
+                        })))
                         .await;
                 } else {
                     let key = Key::from_bucket(bucket.borrow(), &path).unwrap();
                     self.ref_manager
                         .as_ref()
                         .unwrap()
-                        .tell(ManagerMessage::Change(Change::DeleteKey { bucket, key }))
+                        .tell(ManagerMessage::Change(Box::new(Change::DeleteKey {
+                            bucket,
+                            key,
+                        })))
                         .await;
                 }
             }
@@ -245,6 +348,8 @@ impl Handler for EventWatcher {
                     return ();
                 };
 
+                self.settle(Settle::Cancel(path.clone())).await;
+
                 let bucket = Bucket::find_bucket(root, &path).unwrap();
                 let key = Key::from_bucket(bucket.borrow(), &path).unwrap();
                 let file_name = path
@@ -255,11 +360,11 @@ impl Handler for EventWatcher {
                 self.ref_manager
                     .as_ref()
                     .unwrap()
-                    .tell(ManagerMessage::Change(Change::DeleteObject {
+                    .tell(ManagerMessage::Change(Box::new(Change::DeleteObject {
                         bucket,
                         key,
                         file_name,
-                    }))
+                    })))
                     .await;
             }
             notify::EventKind::Remove(RemoveKind::Any | RemoveKind::Other) => {
@@ -282,7 +387,9 @@ impl Handler for EventWatcher {
                             .tell(Event::new(notify::EventKind::Remove(RemoveKind::File)))
                             .await;
                     }
-                    ManagerReply::None => {}
+                    ManagerReply::None
+                    | ManagerReply::Reconciling(_)
+                    | ManagerReply::Reconciled(_) => {}
                 }
             }
             _ => {}
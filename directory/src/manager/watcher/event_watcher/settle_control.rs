@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify::{
+    Event,
+    event::{AccessKind, AccessMode},
+};
+use tokio::{
+    sync::mpsc::{UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+};
+
+use crate::{
+    actor::{Actor, ActorRef, Context, Envelope, Handler},
+    manager::watcher::event_watcher::EventWatcher,
+};
+
+pub struct SettleControl {
+    window: Duration,
+    sender_watcher: <EventWatcher as Actor>::ActorRef,
+    tasks: HashMap<PathBuf, (Pending, JoinHandle<()>)>,
+}
+
+impl SettleControl {
+    pub fn new(sender_watcher: <EventWatcher as Actor>::ActorRef, window: Duration) -> Self {
+        Self {
+            sender_watcher,
+            window,
+            tasks: HashMap::new(),
+        }
+    }
+
+    fn watch(&self, path: PathBuf) -> JoinHandle<()> {
+        let sender_watcher = self.sender_watcher.clone();
+        let window = self.window;
+
+        tokio::spawn(async move {
+            let mut last = stat(&path).await;
+            loop {
+                tokio::time::sleep(window).await;
+                let current = stat(&path).await;
+                if current.is_none() {
+                    return;
+                }
+                if current == last {
+                    break;
+                }
+                last = current;
+            }
+
+            tracing::trace!("[ SettleControl ] {path:?} stable for {window:?}");
+            let event = Event::new(notify::EventKind::Access(AccessKind::Close(
+                AccessMode::Write,
+            )))
+            .add_path(path);
+            sender_watcher.tell(event).await;
+        })
+    }
+}
+
+async fn stat(path: &Path) -> Option<(u64, Option<std::time::SystemTime>)> {
+    tokio::fs::metadata(path)
+        .await
+        .ok()
+        .map(|x| (x.len(), x.modified().ok()))
+}
+
+impl Actor for SettleControl {
+    type Reply = Option<Pending>;
+    type Message = Settle;
+    type Context = Context<Self>;
+    type ActorRef = ActorRef<UnboundedSender<Envelope<Self>>, Self>;
+
+    fn start(mut self) -> Self::ActorRef {
+        let (tx, mut rx) = unbounded_channel();
+        let self_ref = ActorRef::new(tx);
+        let mut ctx = Context::new(self_ref.clone());
+        tokio::spawn(async move {
+            while let Some(Envelope { message, reply_to }) = rx.recv().await {
+                let reply = self.handle(message, &mut ctx).await;
+                if let Some(reply_to) = reply_to
+                    && reply_to.send(reply).is_err()
+                {
+                    tracing::error!("[ SettleControl ] error reply");
+                }
+            }
+        });
+
+        self_ref
+    }
+}
+
+impl Handler for SettleControl {
+    async fn handle(&mut self, message: Self::Message, _ctx: &mut Self::Context) -> Self::Reply {
+        match message {
+            Settle::Track(path, pending) => {
+                let pending = match self.tasks.remove(&path) {
+                    Some((Pending::New, handler)) => {
+                        handler.abort();
+                        Pending::New
+                    }
+                    Some((_, handler)) => {
+                        handler.abort();
+                        pending
+                    }
+                    None => {
+                        tracing::trace!("[ SettleControl ] Track {path:?} {pending:?}");
+                        pending
+                    }
+                };
+
+                let handler = self.watch(path.clone());
+                self.tasks.insert(path, (pending, handler));
+                None
+            }
+            Settle::Done(path) => self.tasks.remove(&path).map(|(pending, handler)| {
+                tracing::trace!("[ SettleControl ] Settled {path:?} {pending:?}");
+                handler.abort();
+                pending
+            }),
+            Settle::Cancel(path) => self.tasks.remove(&path).map(|(pending, handler)| {
+                tracing::trace!("[ SettleControl ] Cancel {path:?} {pending:?}");
+                handler.abort();
+                pending
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pending {
+    New,
+    Update,
+}
+
+#[derive(Debug)]
+pub enum Settle {
+    Track(PathBuf, Pending),
+    Done(PathBuf),
+    Cancel(PathBuf),
+}
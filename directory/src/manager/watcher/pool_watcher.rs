@@ -1,33 +1,67 @@
 use std::{
+    collections::{HashMap, HashSet},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
     time::Duration,
 };
 
-use notify::{Config, PollWatcher, Watcher};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use notify::{
+    Config, Event, EventKind, PollWatcher, Watcher,
+    event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode},
+};
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+};
 
-use crate::manager::watcher::error::WatcherErr;
+use crate::{
+    actor::{Actor, ActorContext, ActorRef, Context, Envelope, Handler},
+    manager::watcher::{error::WatcherErr, event_watcher::EventWatcher},
+};
 
-pub struct PollWatcherNotifyCh<Tx>(Tx);
+const BATCH_QUIET: Duration = Duration::from_millis(250);
 
-impl<Tx> PollWatcherNotifyCh<Tx>
-where
-    Tx: Clone,
-{
-    fn new(tx: Tx) -> Self {
-        Self(tx)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Inode {
+    dev: u64,
+    ino: u64,
+    is_dir: bool,
+}
+
+impl From<std::fs::Metadata> for Inode {
+    fn from(value: std::fs::Metadata) -> Self {
+        Self {
+            dev: value.dev(),
+            ino: value.ino(),
+            is_dir: value.is_dir(),
+        }
     }
 }
 
+#[derive(Debug, Default)]
+struct Batch {
+    created: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    modified: Vec<PathBuf>,
+}
+
 pub struct PollWatcherNotify {
     _poll_watcher: PollWatcher,
-    tx: UnboundedSender<Result<notify::Event, notify::Error>>,
-    rx: Option<UnboundedReceiver<Result<notify::Event, notify::Error>>>,
-    path: String,
+    tx: UnboundedSender<Envelope<Self>>,
+    rx: Option<UnboundedReceiver<Envelope<Self>>>,
+    path: PathBuf,
+    sender_watcher: <EventWatcher as Actor>::ActorRef,
+    inodes: HashMap<PathBuf, Inode>,
+    batch: Batch,
+    flush: Option<JoinHandle<()>>,
 }
 
 impl PollWatcherNotify {
-    pub fn new<T: AsRef<Path>>(real_path: T, interval: u64) -> Result<Self, WatcherErr> {
+    pub fn new<T: AsRef<Path>>(
+        real_path: T,
+        interval: Duration,
+        sender_watcher: <EventWatcher as Actor>::ActorRef,
+    ) -> Result<Self, WatcherErr> {
         let mut path = PathBuf::from(real_path.as_ref());
 
         if path.is_relative() {
@@ -39,12 +73,16 @@ impl PollWatcherNotify {
         let (tx, rx) = unbounded_channel();
         let tx_cp = tx.clone();
         let mut poll = PollWatcher::new(
-            move |ev| {
-                if let Err(err) = tx_cp.send(ev) {
-                    tracing::error!("[Watcher] {{ Inner Task Error }} {err}");
+            move |ev| match ev {
+                Ok(ev) => {
+                    tracing::info!("[ New Poll Event ]: {ev:?}");
+                    if let Err(err) = tx_cp.send(Envelope::tell(PollMessage::Event(ev))) {
+                        tracing::error!("[Watcher] {{ Inner Task Error }} {err}");
+                    }
                 }
+                Err(er) => tracing::error!("[ Poll Error ]: {er}"),
             },
-            Config::default().with_poll_interval(Duration::from_millis(interval)),
+            Config::default().with_poll_interval(interval),
         )
         .map_err(|x| WatcherErr::new(x.to_string()))?;
 
@@ -55,7 +93,244 @@ impl PollWatcherNotify {
             tx,
             rx: Some(rx),
             _poll_watcher: poll,
-            path: real_path.as_ref().to_string_lossy().into_owned(),
+            path,
+            sender_watcher,
+            inodes: HashMap::new(),
+            batch: Batch::default(),
+            flush: None,
         })
     }
+
+    async fn index(&mut self) {
+        let mut stack = vec![self.path.clone()];
+        while let Some(dir) = stack.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let Ok(md) = entry.metadata().await else {
+                    continue;
+                };
+                let path = entry.path();
+                if md.is_dir() {
+                    stack.push(path.clone());
+                }
+                self.inodes.insert(path, md.into());
+            }
+        }
+        tracing::debug!("[ PollWatcher ] Indexed {} paths", self.inodes.len());
+    }
+
+    fn push(&mut self, event: Event) {
+        let list = match event.kind {
+            EventKind::Create(_) => &mut self.batch.created,
+            EventKind::Remove(_) => &mut self.batch.removed,
+            EventKind::Modify(_) => &mut self.batch.modified,
+            _ => return,
+        };
+        list.extend(event.paths);
+    }
+
+    fn rekey(&mut self, from: &Path, to: &Path) {
+        let moved = self
+            .inodes
+            .keys()
+            .filter(|x| x.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for path in moved {
+            if let Some(inode) = self.inodes.remove(&path) {
+                let path = match path.strip_prefix(from) {
+                    Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                    _ => to.to_path_buf(),
+                };
+                self.inodes.insert(path, inode);
+            }
+        }
+    }
+
+    fn forget(&mut self, path: &Path) -> Option<Inode> {
+        let inode = self.inodes.remove(path);
+        self.inodes.retain(|x, _| !x.starts_with(path));
+        inode
+    }
+
+    async fn emit(&self, kind: EventKind, paths: Vec<PathBuf>) {
+        let event = paths
+            .into_iter()
+            .fold(Event::new(kind), |ev, path| ev.add_path(path));
+        self.sender_watcher.tell(event).await;
+    }
+
+    async fn flush(&mut self) {
+        let Batch {
+            created,
+            removed,
+            modified,
+        } = std::mem::take(&mut self.batch);
+
+        tracing::debug!(
+            "[ PollWatcher ] Flush {} created, {} removed, {} modified",
+            created.len(),
+            removed.len(),
+            modified.len()
+        );
+
+        let mut created_inodes = HashMap::new();
+        for path in created.into_iter().collect::<HashSet<_>>() {
+            match tokio::fs::metadata(&path).await {
+                Ok(md) => {
+                    created_inodes.insert(path, Inode::from(md));
+                }
+                Err(er) => tracing::debug!("[ PollWatcher ] {path:?} vanished: {er}"),
+            }
+        }
+
+        let mut removed = removed
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|x| self.inodes.get(&x).copied().map(|inode| (x, inode)))
+            .collect::<HashMap<_, _>>();
+
+        let by_inode = removed
+            .iter()
+            .map(|(path, inode)| ((inode.dev, inode.ino), path.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let pairs = created_inodes
+            .iter()
+            .filter_map(|(to, inode)| {
+                by_inode
+                    .get(&(inode.dev, inode.ino))
+                    .map(|from| (from.clone(), to.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        let mut renames = pairs
+            .iter()
+            .filter(|(from, _)| {
+                !pairs
+                    .iter()
+                    .any(|(other, _)| other != from && from.starts_with(other))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        renames.sort_by_key(|(from, _)| from.components().count());
+
+        for (from, to) in &pairs {
+            removed.remove(from);
+            created_inodes.remove(to);
+        }
+
+        for (from, to) in renames {
+            tracing::debug!("[ PollWatcher ] Rename {from:?} -> {to:?}");
+            self.rekey(&from, &to);
+            self.emit(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec![from, to],
+            )
+            .await;
+        }
+
+        let mut deleted = removed
+            .keys()
+            .filter(|x| {
+                !removed
+                    .keys()
+                    .any(|other| other != *x && x.starts_with(other))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        deleted.sort();
+
+        for path in deleted {
+            let Some(inode) = self.forget(&path) else {
+                continue;
+            };
+            let kind = if inode.is_dir {
+                RemoveKind::Folder
+            } else {
+                RemoveKind::File
+            };
+            self.emit(EventKind::Remove(kind), vec![path]).await;
+        }
+
+        let mut created = created_inodes.into_iter().collect::<Vec<_>>();
+        created.sort_by_key(|(path, _)| path.components().count());
+
+        for (path, inode) in created {
+            self.inodes.insert(path.clone(), inode);
+            let kind = if inode.is_dir {
+                CreateKind::Folder
+            } else {
+                CreateKind::File
+            };
+            self.emit(EventKind::Create(kind), vec![path]).await;
+        }
+
+        for path in modified.into_iter().collect::<HashSet<_>>() {
+            if path.is_file() {
+                self.emit(
+                    EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+                    vec![path],
+                )
+                .await;
+            }
+        }
+    }
+}
+
+impl Actor for PollWatcherNotify {
+    type Message = PollMessage;
+    type Reply = ();
+    type Context = Context<Self>;
+    type ActorRef = ActorRef<UnboundedSender<Envelope<Self>>, Self>;
+
+    fn start(mut self) -> Self::ActorRef {
+        let mut rx = self.rx.take().unwrap();
+        let self_ref = ActorRef::new(self.tx.clone());
+        let mut ctx = Context::new(self_ref.clone());
+
+        tokio::spawn(async move {
+            tracing::info!("[ PollWatcher Init ] {:?}", self.path);
+            self.index().await;
+            while let Some(Envelope { message, .. }) = rx.recv().await {
+                self.handle(message, &mut ctx).await;
+            }
+        });
+
+        self_ref
+    }
+}
+
+impl Handler for PollWatcherNotify {
+    async fn handle(&mut self, message: Self::Message, ctx: &mut Self::Context) -> Self::Reply {
+        match message {
+            PollMessage::Event(event) => {
+                self.push(event);
+
+                if let Some(handler) = self.flush.take() {
+                    handler.abort();
+                }
+
+                let self_ref = ctx.actor_ref().clone();
+                self.flush = Some(tokio::spawn(async move {
+                    tokio::time::sleep(BATCH_QUIET).await;
+                    self_ref.tell(PollMessage::Flush).await;
+                }));
+            }
+            PollMessage::Flush => {
+                self.flush = None;
+                self.flush().await;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PollMessage {
+    Event(Event),
+    Flush,
 }
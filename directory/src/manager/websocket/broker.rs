@@ -1,11 +1,17 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use hyper_tungstenite::tungstenite;
+use futures::{FutureExt, future::join_all};
 use uuid::Uuid;
 
 use crate::{
     actor::{Actor, ActorRef, Context, Envelope},
-    manager::websocket::observer::{Observer, UserObserver},
+    manager::{
+        change_log::ChangeRecord,
+        websocket::{
+            observer::{Delivery, Observer, UserObserver},
+            protocol::CloseReason,
+        },
+    },
 };
 
 #[derive(Default)]
@@ -58,15 +64,49 @@ impl Actor for WSBroker {
                         ..
                     }) => {
                         tracing::debug!(
-                            "[ WSBroker ] Send message {{ {msg:?} }} to {} observers",
+                            "[ WSBroker ] Send change {} to {} observers",
+                            msg.seq,
                             self.observers.len()
                         );
-                        for (_, i) in &mut self.observers {
-                            i.update(msg.clone()).await;
+                        let deliveries = join_all(
+                            self.observers
+                                .iter_mut()
+                                .map(|(id, i)| i.update(msg.clone()).map(move |x| (*id, x))),
+                        )
+                        .await;
+
+                        for (id, delivery) in deliveries {
+                            match delivery {
+                                Delivery::Delivered => {}
+                                Delivery::Dropped => {
+                                    tracing::debug!("[ WSBroker ] Message dropped for {id}");
+                                }
+                                Delivery::Evicted => {
+                                    if let Some(observer) = self.observers.remove(&id) {
+                                        tracing::warn!(
+                                            "[ WSBroker ] Slow observer {id} evicted {observer:?}"
+                                        );
+                                        observer.evict().await;
+                                    }
+                                }
+                            }
                         }
                     }
 
-                    None => {}
+                    Some(Envelope {
+                        message: WSBrokerMessage::Close(reason),
+                        ..
+                    }) => {
+                        tracing::debug!(
+                            "[ WSBroker ] Close {} observers, reason: {reason}",
+                            self.observers.len()
+                        );
+                        for (id, observer) in self.observers.drain() {
+                            observer.close(id, reason).await;
+                        }
+                        break;
+                    }
+                    None => break,
                 }
             }
         });
@@ -78,5 +118,6 @@ impl Actor for WSBroker {
 pub enum WSBrokerMessage {
     Subscriber(UserObserver),
     Ubsubscriber(uuid::Uuid),
-    Message(tungstenite::Message),
+    Message(Arc<ChangeRecord>),
+    Close(CloseReason),
 }
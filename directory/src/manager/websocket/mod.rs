@@ -1,26 +1,402 @@
 pub mod broker;
 pub mod observer;
+pub mod protocol;
 
-use futures::{SinkExt, stream::SplitSink};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::ControlFlow,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
 use hyper::upgrade::Upgraded;
 use hyper_tungstenite::{WebSocketStream, tungstenite};
 use hyper_util::rt::TokioIo;
+use tokio::{
+    sync::{RwLock, broadcast::error::RecvError},
+    time::Instant,
+};
+use uuid::Uuid;
 
 use crate::{
-    actor::{Actor, ActorRef, ActorRefWithShutdown, Context, Envelope},
-    manager::websocket::{
-        broker::{WSBroker, WSBrokerMessage},
-        observer::UserObserver,
+    actor::{Actor, ActorContext, ActorRef, ActorRefWithShutdown, Context, Envelope},
+    bucket::bucket_map::BucketMap,
+    grpc_v1::{ConnectionAuthMS, Permissions, permission_cache::PermissionChange},
+    manager::{
+        change_log::ChangeLog,
+        websocket::{
+            broker::{WSBroker, WSBrokerMessage},
+            observer::{DeliveryPolicy, Outbox, UserObserver},
+            protocol::{ClientMessage, CloseReason, ServerMessage, Subscription},
+        },
     },
 };
 
+const DELIVERED_WINDOW: usize = 1024;
+
+type WebSocket = WebSocketStream<TokioIo<Upgraded>>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WebSocketConfig {
+    pub buffer: usize,
+    pub policy: DeliveryPolicy,
+    pub ping_interval: Duration,
+    pub pong_timeout: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl std::default::Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            buffer: 32,
+            policy: DeliveryPolicy::DropOldest,
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
 pub struct WebSocketHandler {
-    pub user: SplitSink<WebSocketStream<TokioIo<Upgraded>>, tungstenite::Message>,
-    pub broker: <WSBroker as Actor>::ActorRef,
+    user: SplitSink<WebSocket, tungstenite::Message>,
+    stream: SplitStream<WebSocket>,
+    tree: Arc<BucketMap>,
+    change_log: Arc<RwLock<ChangeLog>>,
+    auth: ConnectionAuthMS,
+    user_id: Uuid,
+    config: WebSocketConfig,
+    outbox: Arc<Outbox>,
+    initial: Option<(Subscription, Option<u64>)>,
+    subscriptions: HashMap<Subscription, (<WSBroker as Actor>::ActorRef, Uuid)>,
+    delivered: BTreeSet<u64>,
+    last_seen: Instant,
+    pong_deadline: Option<Instant>,
+}
+
+impl WebSocketHandler {
+    pub fn new(
+        ws: WebSocket,
+        tree: Arc<BucketMap>,
+        change_log: Arc<RwLock<ChangeLog>>,
+        auth: ConnectionAuthMS,
+        user_id: Uuid,
+        config: WebSocketConfig,
+        initial: Option<(Subscription, Option<u64>)>,
+    ) -> Self {
+        let (user, stream) = ws.split();
+        Self {
+            user,
+            stream,
+            tree,
+            change_log,
+            auth,
+            user_id,
+            config,
+            outbox: Arc::new(Outbox::new(config.buffer)),
+            initial,
+            subscriptions: HashMap::new(),
+            delivered: BTreeSet::new(),
+            last_seen: Instant::now(),
+            pong_deadline: None,
+        }
+    }
+
+    async fn subscribe(
+        &mut self,
+        subscription: Subscription,
+        resume_from: Option<u64>,
+        ctx: &<Self as Actor>::Context,
+    ) -> Result<(), tungstenite::Error> {
+        if self.subscriptions.contains_key(&subscription) {
+            let seq = self.change_log.read().await.last_seq();
+            return self
+                .user
+                .send(
+                    ServerMessage::Subscribed {
+                        subscription: &subscription,
+                        seq,
+                    }
+                    .into(),
+                )
+                .await;
+        }
+
+        if !self
+            .auth
+            .is_allowed(
+                self.user_id,
+                subscription.bucket().map(|x| x.name()),
+                Permissions::Read,
+            )
+            .await
+            .unwrap_or_default()
+        {
+            tracing::debug!(
+                "[ WebSocketHandler ] Subscribe, {subscription} forbidden for {}",
+                self.user_id
+            );
+            return self
+                .user
+                .send(ServerMessage::error(format!("{subscription} forbidden")).into())
+                .await;
+        }
+
+        let Some(broker) = self
+            .tree
+            .broker(subscription.bucket(), subscription.key())
+            .await
+        else {
+            tracing::debug!("[ WebSocketHandler ] Subscribe, {subscription} not found");
+            return self
+                .user
+                .send(ServerMessage::error(format!("{subscription} not found")).into())
+                .await;
+        };
+
+        let observer = UserObserver::new(
+            ctx.actor_ref().clone(),
+            self.outbox.clone(),
+            self.config.policy,
+        );
+        let id = broker.ask(WSBrokerMessage::Subscriber(observer)).await;
+        tracing::debug!("[ WebSocketHandler ] New Observer {id} on {subscription}");
+
+        let (seq, backlog) = {
+            let log = self.change_log.read().await;
+            (log.last_seq(), resume_from.map(|x| log.since(x)))
+        };
+
+        let reply = match backlog {
+            Some(None) => {
+                tracing::debug!(
+                    "[ WebSocketHandler ] {subscription} can't resume from {resume_from:?}, last sequence {seq}"
+                );
+                ServerMessage::ResyncRequired {
+                    subscription: &subscription,
+                    seq,
+                }
+            }
+            _ => ServerMessage::Subscribed {
+                subscription: &subscription,
+                seq,
+            },
+        }
+        .into();
+        self.user.feed(reply).await?;
+
+        for record in backlog.flatten().unwrap_or_default() {
+            let visible = if record.resync {
+                subscription.overlaps(record.bucket.as_ref(), record.key.as_ref())
+            } else {
+                subscription.covers(record.bucket.as_ref(), record.key.as_ref())
+                    || record.destination.as_ref().is_some_and(|(bucket, key)| {
+                        subscription.covers(bucket.as_ref(), key.as_ref())
+                    })
+            };
+
+            if visible && self.mark_delivered(record.seq) {
+                self.user.feed(record.message()).await?;
+            }
+        }
+
+        self.subscriptions.insert(subscription, (broker, id));
+        self.user.flush().await
+    }
+
+    fn mark_delivered(&mut self, seq: u64) -> bool {
+        if !self.delivered.insert(seq) {
+            return false;
+        }
+        while self.delivered.len() > DELIVERED_WINDOW {
+            self.delivered.pop_first();
+        }
+        true
+    }
+
+    async fn unsubscribe(&mut self, subscription: Subscription) -> tungstenite::Message {
+        match self.subscriptions.remove(&subscription) {
+            Some((broker, id)) => {
+                broker.tell(WSBrokerMessage::Ubsubscriber(id)).await;
+                tracing::debug!("[ WebSocketHandler ] Unsubscriber {id} from {subscription}");
+                ServerMessage::Unsubscribed(&subscription).into()
+            }
+            None => ServerMessage::error(format!("{subscription} isn't subscribed")).into(),
+        }
+    }
+
+    async fn close(&mut self, reason: CloseReason) {
+        tracing::debug!("[ WebSocketHandler ] Close connection, reason: {reason}");
+        if let Err(er) = self
+            .user
+            .send(tungstenite::Message::Close(Some(reason.frame())))
+            .await
+        {
+            tracing::debug!("[ WebSocketHandler ] Close error: {er:?}");
+        }
+    }
+
+    async fn on_command(&mut self, command: WebSocketCommand) -> ControlFlow<()> {
+        match command {
+            WebSocketCommand::Close(reason) => {
+                self.close(reason).await;
+                ControlFlow::Break(())
+            }
+            WebSocketCommand::SubscriptionClosed { id, reason } => {
+                let Some(subscription) = self
+                    .subscriptions
+                    .iter()
+                    .find_map(|(k, (_, x))| (*x == id).then(|| k.clone()))
+                else {
+                    return ControlFlow::Continue(());
+                };
+                self.subscriptions.remove(&subscription);
+
+                let msg = ServerMessage::SubscriptionClosed {
+                    subscription: &subscription,
+                    reason,
+                };
+                if self.user.send(msg.into()).await.is_err() {
+                    return ControlFlow::Break(());
+                }
+
+                if self.subscriptions.is_empty() {
+                    self.close(reason).await;
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            }
+        }
+    }
+
+    async fn on_permission_change(&mut self, change: PermissionChange) -> ControlFlow<()> {
+        if !change.affects(self.user_id)
+            || change.bucket.as_ref().is_some_and(|bucket| {
+                !self
+                    .subscriptions
+                    .keys()
+                    .any(|x| x.bucket().is_none_or(|x| x.name() == bucket))
+            })
+        {
+            return ControlFlow::Continue(());
+        }
+
+        let mut revoked = Vec::new();
+        for subscription in self.subscriptions.keys() {
+            match self
+                .auth
+                .is_allowed(
+                    self.user_id,
+                    subscription.bucket().map(|x| x.name()),
+                    Permissions::Read,
+                )
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => revoked.push(subscription.clone()),
+                Err(_) => tracing::warn!(
+                    "[ WebSocketHandler ] Permission check on {subscription} for {} failed, keeping it",
+                    self.user_id
+                ),
+            }
+        }
+
+        if revoked.is_empty() {
+            return ControlFlow::Continue(());
+        }
+
+        for subscription in revoked {
+            if let Some((broker, id)) = self.subscriptions.remove(&subscription) {
+                broker.tell(WSBrokerMessage::Ubsubscriber(id)).await;
+            }
+            tracing::info!(
+                "[ WebSocketHandler ] Permission revoked on {subscription} for {}",
+                self.user_id
+            );
+
+            let msg = ServerMessage::SubscriptionClosed {
+                subscription: &subscription,
+                reason: CloseReason::PermissionRevoked,
+            };
+            if self.user.send(msg.into()).await.is_err() {
+                return ControlFlow::Break(());
+            }
+        }
+
+        if self.subscriptions.is_empty() {
+            self.close(CloseReason::PermissionRevoked).await;
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    async fn flush_outbox(&mut self) -> Result<(), tungstenite::Error> {
+        while let Some(record) = self.outbox.pop() {
+            if self.mark_delivered(record.seq) {
+                self.user.feed(record.message()).await?;
+            }
+        }
+        self.user.flush().await
+    }
+
+    async fn on_client(
+        &mut self,
+        message: tungstenite::Message,
+        ctx: &<Self as Actor>::Context,
+    ) -> Result<ControlFlow<()>, tungstenite::Error> {
+        self.last_seen = Instant::now();
+        let text = match message {
+            tungstenite::Message::Text(text) => text,
+            tungstenite::Message::Pong(_) => {
+                self.pong_deadline = None;
+                return Ok(ControlFlow::Continue(()));
+            }
+            tungstenite::Message::Close(frame) => {
+                tracing::debug!("[ WebSocketHandler ] Close from peer {frame:?}");
+                return Ok(ControlFlow::Break(()));
+            }
+            _ => return Ok(ControlFlow::Continue(())),
+        };
+
+        let reply = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Subscribe {
+                bucket,
+                key,
+                resume_from,
+            }) => {
+                return self
+                    .subscribe(Subscription::new(bucket, key), resume_from, ctx)
+                    .await
+                    .map(|_| ControlFlow::Continue(()));
+            }
+            Ok(ClientMessage::Unsubscribe { bucket, key }) => {
+                self.unsubscribe(Subscription::new(bucket, key)).await
+            }
+            Ok(ClientMessage::ListSubscriptions) => ServerMessage::Subscriptions {
+                items: self.subscriptions.keys().collect(),
+            }
+            .into(),
+            Ok(ClientMessage::Ping) => ServerMessage::Pong.into(),
+            Err(er) => {
+                tracing::debug!("[ WebSocketHandler ] Invalid message {text}: {er}");
+                ServerMessage::error(er).into()
+            }
+        };
+
+        self.user
+            .send(reply)
+            .await
+            .map(|_| ControlFlow::Continue(()))
+    }
 }
 
 impl Actor for WebSocketHandler {
-    type Message = tungstenite::Message;
+    type Message = WebSocketCommand;
     type Reply = ();
     type ActorRef = ActorRefWithShutdown<tokio::sync::mpsc::Sender<Envelope<Self>>, Self>;
     type Context = Context<Self>;
@@ -30,38 +406,124 @@ impl Actor for WebSocketHandler {
         let (tx_shut, mut rx_shut) = tokio::sync::oneshot::channel();
         let actor_ref = ActorRefWithShutdown::new(ActorRef::new(tx.clone()), tx_shut);
         let actor_ref_clone = actor_ref.clone();
-        let user_obs = UserObserver::new(actor_ref.clone());
 
         tokio::spawn(async move {
-            let _context = Context::<Self>::new(actor_ref_clone);
-            let id = self.broker.ask(WSBrokerMessage::Subscriber(user_obs)).await;
-            tracing::debug!("[ WebSocketHandler ] New Observer {id}");
+            let context = Context::<Self>::new(actor_ref_clone);
+            let outbox = self.outbox.clone();
+            let WebSocketConfig {
+                ping_interval,
+                idle_timeout,
+                ..
+            } = self.config;
+            let mut permissions = self.auth.changes();
+            let mut permissions_open = true;
+            let mut ping = tokio::time::interval_at(
+                Instant::now() + ping_interval,
+                ping_interval.max(Duration::from_secs(1)),
+            );
+
+            if let Some((subscription, resume_from)) = self.initial.take()
+                && let Err(er) = self.subscribe(subscription, resume_from, &context).await
+            {
+                tracing::error!("[ WebSocketHandler ] error: {er:?}");
+            }
+
             loop {
+                let idle_deadline =
+                    (!idle_timeout.is_zero()).then(|| self.last_seen + idle_timeout);
+                let pong_deadline = self.pong_deadline;
+
                 tokio::select! {
                     message = rx.recv() => {
                         tracing::debug!("[ WebSocketHandler ] New message from Actor receiver: {message:?}");
                         match message {
-                            Some(Envelope { message, .. }) => if let Err(er) = self.user.send(message).await {
-                                tracing::error!("[ WebSocketHandler ] error: {er:?}");
+                            Some(Envelope { message, .. }) => if self.on_command(message).await.is_break() {
                                 break;
                             },
                             None => { break; },
                         }
                     },
+                    _ = outbox.notified() => {
+                        if let Err(er) = self.flush_outbox().await {
+                            tracing::error!("[ WebSocketHandler ] error: {er:?}");
+                            break;
+                        }
+                    },
+                    inbound = self.stream.next() => {
+                        match inbound {
+                            Some(Ok(msg)) => match self.on_client(msg, &context).await {
+                                Ok(ControlFlow::Continue(())) => {}
+                                Ok(ControlFlow::Break(())) => break,
+                                Err(er) => {
+                                    tracing::error!("[ WebSocketHandler ] error: {er:?}");
+                                    break;
+                                }
+                            },
+                            Some(Err(er)) => {
+                                tracing::error!("[ WebSocketHandler ] Peer error {er}");
+                                break;
+                            }
+                            None => break,
+                        }
+                    },
+                    _ = ping.tick(), if !ping_interval.is_zero() => {
+                        if let Err(er) = self.user.send(tungstenite::Message::Ping(Default::default())).await {
+                            tracing::error!("[ WebSocketHandler ] Ping error: {er:?}");
+                            break;
+                        }
+                        self.pong_deadline.get_or_insert(Instant::now() + self.config.pong_timeout);
+                    },
+                    _ = deadline(pong_deadline) => {
+                        self.close(CloseReason::PongTimeout).await;
+                        break;
+                    },
+                    _ = deadline(idle_deadline) => {
+                        self.close(CloseReason::IdleTimeout).await;
+                        break;
+                    },
+                    change = permissions.recv(), if permissions_open => {
+                        let change = match change {
+                            Ok(change) => change,
+                            Err(RecvError::Lagged(_)) => PermissionChange { user_id: None, bucket: None },
+                            Err(RecvError::Closed) => {
+                                permissions_open = false;
+                                continue;
+                            }
+                        };
+                        if self.on_permission_change(change).await.is_break() {
+                            break;
+                        }
+                    },
                     _ = &mut rx_shut => {
-                        _ = self.user.close().await;
+                        self.close(CloseReason::Shutdown).await;
                         break;
                     }
                 }
             }
-            self.broker.tell(WSBrokerMessage::Ubsubscriber(id)).await;
-            tracing::debug!("[ WebSocketHandler ] Unsubscriber {id}");
+
+            for (subscription, (broker, id)) in self.subscriptions.drain() {
+                broker.tell(WSBrokerMessage::Ubsubscriber(id)).await;
+                tracing::debug!("[ WebSocketHandler ] Unsubscriber {id} from {subscription}");
+            }
         });
 
         actor_ref
     }
 }
 
+async fn deadline(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[derive(Debug)]
+pub enum WebSocketCommand {
+    Close(CloseReason),
+    SubscriptionClosed { id: Uuid, reason: CloseReason },
+}
+
 impl std::fmt::Debug for WebSocketHandler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "WebSocketHandler {{ .. }}")
@@ -1,33 +1,152 @@
-use std::pin::Pin;
+use std::{collections::VecDeque, pin::Pin, sync::Arc};
 
 use futures::FutureExt;
-use hyper_tungstenite::tungstenite;
+use tokio::sync::Notify;
+use uuid::Uuid;
 
-use crate::{actor::Actor, manager::websocket::WebSocketHandler};
+use crate::{
+    actor::Actor,
+    manager::{
+        change_log::ChangeRecord,
+        websocket::{WebSocketCommand, WebSocketHandler, protocol::CloseReason},
+    },
+};
 
 pub trait Observer {
     type Event: Send + 'static;
-    fn update(&mut self, ev: Self::Event) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+    fn update(&mut self, ev: Self::Event) -> Pin<Box<dyn Future<Output = Delivery> + Send + '_>>;
 }
 
-pub struct UserObserver(<WebSocketHandler as Actor>::ActorRef);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered,
+    Dropped,
+    Evicted,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DeliveryPolicy {
+    DropOldest,
+    DropNewest,
+    Disconnect { max_missed: usize },
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    queue: std::sync::Mutex<VecDeque<Arc<ChangeRecord>>>,
+    capacity: usize,
+    notify: Notify,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: std::sync::Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn try_push(&self, msg: Arc<ChangeRecord>) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.capacity {
+            return false;
+        }
+        queue.push_back(msg);
+        drop(queue);
+        self.notify.notify_one();
+        true
+    }
+
+    pub fn force_push(&self, msg: Arc<ChangeRecord>) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let dropped = queue.len() >= self.capacity && queue.pop_front().is_some();
+        queue.push_back(msg);
+        drop(queue);
+        self.notify.notify_one();
+        !dropped
+    }
+
+    pub fn pop(&self) -> Option<Arc<ChangeRecord>> {
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    pub async fn notified(&self) {
+        self.notify.notified().await
+    }
+}
+
+pub struct UserObserver {
+    actor: <WebSocketHandler as Actor>::ActorRef,
+    outbox: Arc<Outbox>,
+    policy: DeliveryPolicy,
+    missed: usize,
+}
 
 impl UserObserver {
-    pub fn new(actor: <WebSocketHandler as Actor>::ActorRef) -> Self {
-        Self(actor)
+    pub fn new(
+        actor: <WebSocketHandler as Actor>::ActorRef,
+        outbox: Arc<Outbox>,
+        policy: DeliveryPolicy,
+    ) -> Self {
+        Self {
+            actor,
+            outbox,
+            policy,
+            missed: 0,
+        }
+    }
+
+    pub async fn evict(self) {
+        self.actor
+            .tell(WebSocketCommand::Close(CloseReason::SlowConsumer))
+            .await;
+    }
+
+    pub async fn close(self, id: Uuid, reason: CloseReason) {
+        self.actor
+            .tell(WebSocketCommand::SubscriptionClosed { id, reason })
+            .await;
     }
 }
 
 impl std::fmt::Debug for UserObserver {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "UserObserver {{ .. }}")
+        f.debug_struct("UserObserver")
+            .field("policy", &self.policy)
+            .field("missed", &self.missed)
+            .finish()
     }
 }
 
 impl Observer for UserObserver {
-    type Event = tungstenite::Message;
+    type Event = Arc<ChangeRecord>;
+
+    fn update(&mut self, ev: Self::Event) -> Pin<Box<dyn Future<Output = Delivery> + Send + '_>> {
+        let delivery = match self.policy {
+            DeliveryPolicy::DropOldest => match self.outbox.force_push(ev) {
+                true => Delivery::Delivered,
+                false => Delivery::Dropped,
+            },
+            DeliveryPolicy::DropNewest => match self.outbox.try_push(ev) {
+                true => Delivery::Delivered,
+                false => Delivery::Dropped,
+            },
+            DeliveryPolicy::Disconnect { max_missed } => {
+                if self.outbox.try_push(ev) {
+                    self.missed = 0;
+                    Delivery::Delivered
+                } else {
+                    self.missed += 1;
+                    if self.missed >= max_missed {
+                        Delivery::Evicted
+                    } else {
+                        Delivery::Dropped
+                    }
+                }
+            }
+        };
 
-    fn update(&mut self, ev: Self::Event) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
-        self.0.tell(ev).boxed()
+        futures::future::ready(delivery).boxed()
     }
 }
@@ -0,0 +1,189 @@
+use hyper_tungstenite::tungstenite::{
+    self,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::bucket::{Bucket, key::Key};
+
+#[derive(Debug, Serialize, Hash, PartialEq, Eq, Clone)]
+pub struct Subscription {
+    bucket: Option<Bucket<'static>>,
+    key: Option<Key<'static>>,
+}
+
+impl Subscription {
+    pub fn new(bucket: Option<Bucket<'static>>, key: Option<Key<'static>>) -> Self {
+        let key = bucket.as_ref().map(|_| key.unwrap_or(Key::root()));
+        Self { bucket, key }
+    }
+
+    pub fn bucket(&self) -> Option<&Bucket<'static>> {
+        self.bucket.as_ref()
+    }
+
+    pub fn key(&self) -> Option<&Key<'static>> {
+        self.key.as_ref()
+    }
+
+    pub fn covers(&self, bucket: Option<&Bucket<'_>>, key: Option<&Key<'_>>) -> bool {
+        let Some(own) = self.bucket.as_ref() else {
+            return true;
+        };
+
+        if bucket.is_none_or(|x| own != x) {
+            return false;
+        }
+
+        match (self.key.as_ref(), key) {
+            (Some(own), _) if own.is_root() => true,
+            (Some(own), Some(key)) => key
+                .name()
+                .strip_prefix(own.name())
+                .is_some_and(|x| x.is_empty() || x.starts_with('/')),
+            _ => false,
+        }
+    }
+
+    pub fn overlaps(&self, bucket: Option<&Bucket<'_>>, key: Option<&Key<'_>>) -> bool {
+        if self.covers(bucket, key) {
+            return true;
+        }
+
+        let Some(bucket) = bucket else {
+            return true;
+        };
+
+        if self.bucket.as_ref().is_none_or(|x| x != bucket) {
+            return false;
+        }
+
+        match (key, self.key.as_ref()) {
+            (None, _) => true,
+            (Some(key), _) if key.is_root() => true,
+            (Some(key), Some(own)) => own
+                .name()
+                .strip_prefix(key.name())
+                .is_some_and(|x| x.is_empty() || x.starts_with('/')),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.bucket, &self.key) {
+            (Some(bucket), Some(key)) => write!(f, "{bucket}/{key}"),
+            (Some(bucket), None) => write!(f, "{bucket}"),
+            _ => write!(f, "/"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe {
+        bucket: Option<Bucket<'static>>,
+        key: Option<Key<'static>>,
+        resume_from: Option<u64>,
+    },
+    Unsubscribe {
+        bucket: Option<Bucket<'static>>,
+        key: Option<Key<'static>>,
+    },
+    ListSubscriptions,
+    Ping,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage<'a> {
+    Subscribed {
+        #[serde(flatten)]
+        subscription: &'a Subscription,
+        seq: u64,
+    },
+    ResyncRequired {
+        #[serde(flatten)]
+        subscription: &'a Subscription,
+        seq: u64,
+    },
+    Unsubscribed(&'a Subscription),
+    Subscriptions {
+        items: Vec<&'a Subscription>,
+    },
+    SubscriptionClosed {
+        #[serde(flatten)]
+        subscription: &'a Subscription,
+        reason: CloseReason,
+    },
+    Pong,
+    Error {
+        detail: String,
+    },
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    Shutdown,
+    IdleTimeout,
+    PongTimeout,
+    SlowConsumer,
+    KeyDeleted,
+    PermissionRevoked,
+}
+
+impl CloseReason {
+    pub fn code(&self) -> CloseCode {
+        match self {
+            Self::Shutdown => CloseCode::Away,
+            Self::IdleTimeout => CloseCode::Library(4000),
+            Self::PongTimeout => CloseCode::Library(4001),
+            Self::SlowConsumer => CloseCode::Library(4002),
+            Self::KeyDeleted => CloseCode::Library(4004),
+            Self::PermissionRevoked => CloseCode::Policy,
+        }
+    }
+
+    pub fn frame(&self) -> CloseFrame {
+        CloseFrame {
+            code: self.code(),
+            reason: self.to_string().into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shutdown => write!(f, "server shutdown"),
+            Self::IdleTimeout => write!(f, "idle timeout"),
+            Self::PongTimeout => write!(f, "pong timeout"),
+            Self::SlowConsumer => write!(f, "slow consumer"),
+            Self::KeyDeleted => write!(f, "key deleted"),
+            Self::PermissionRevoked => write!(f, "permission revoked"),
+        }
+    }
+}
+
+impl<'a> ServerMessage<'a> {
+    pub fn error<T: std::fmt::Display>(detail: T) -> Self {
+        Self::Error {
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl<'a> From<ServerMessage<'a>> for tungstenite::Message {
+    fn from(value: ServerMessage<'a>) -> Self {
+        match serde_json::to_string(&value) {
+            Ok(msg) => tungstenite::Message::text(msg),
+            Err(er) => {
+                tracing::error!("[ ServerMessage ] serialize error: {er}");
+                tungstenite::Message::text(r#"{"type":"error"}"#)
+            }
+        }
+    }
+}
@@ -4,6 +4,8 @@ use mongodb::error::{Error, WriteFailure};
 pub enum LsError {
     DuplicateKey,
     MongoDb(Box<dyn std::error::Error + Send>),
+    Sqlite(Box<dyn std::error::Error + Send>),
+    Encoding(serde_json::Error),
 }
 
 impl From<Error> for LsError {
@@ -17,11 +19,28 @@ impl From<Error> for LsError {
     }
 }
 
+impl From<sqlx::Error> for LsError {
+    fn from(value: sqlx::Error) -> Self {
+        match value.as_database_error() {
+            Some(er) if er.is_unique_violation() => Self::DuplicateKey,
+            _ => Self::Sqlite(Box::new(value)),
+        }
+    }
+}
+
+impl From<serde_json::Error> for LsError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Encoding(value)
+    }
+}
+
 impl std::fmt::Display for LsError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LsError::DuplicateKey => write!(f, "Duplicate Key"),
             LsError::MongoDb(error) => write!(f, "{error}"),
+            LsError::Sqlite(error) => write!(f, "{error}"),
+            LsError::Encoding(error) => write!(f, "{error}"),
         }
     }
 }
@@ -0,0 +1,234 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use tokio::sync::RwLock;
+use tonic::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    bucket::{Bucket, Cowed, key::Key, object::Object},
+    manager::{KeyLocation, ObjectLocation, change_log::ChangeRecord},
+    state::local_storage::{MetadataStore, error::LsError},
+};
+
+type Location = (Bucket<'static>, Key<'static>, String);
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: RwLock<BTreeMap<Location, Object>>,
+    changes: RwLock<VecDeque<ChangeRecord>>,
+    capacity: AtomicUsize,
+}
+
+fn location(bucket: Bucket<'_>, key: Key<'_>, file_name: &str) -> Location {
+    (bucket.cloned(), key.cloned(), file_name.to_string())
+}
+
+fn relocate(
+    objects: &mut BTreeMap<Location, Object>,
+    moves: Vec<(Location, Location)>,
+) -> Result<u64, LsError> {
+    let sources = moves.iter().map(|(from, _)| from).collect::<BTreeSet<_>>();
+    if moves
+        .iter()
+        .any(|(_, to)| objects.contains_key(to) && !sources.contains(to))
+    {
+        return Err(LsError::DuplicateKey);
+    }
+
+    let moved = moves
+        .into_iter()
+        .filter_map(|(from, to)| objects.remove(&from).map(|object| (to, object)))
+        .collect::<Vec<_>>();
+
+    let count = moved.len() as u64;
+    for (to, mut object) in moved {
+        object.file_name = to.2.clone();
+        objects.insert(to, object);
+    }
+    Ok(count)
+}
+
+#[async_trait]
+impl MetadataStore for MemoryStorage {
+    async fn new_object(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        object: &Object,
+    ) -> Result<(), LsError> {
+        let mut objects = self.objects.write().await;
+        let location = location(bucket, key, &object.file_name);
+        if objects.contains_key(&location) {
+            return Err(LsError::DuplicateKey);
+        }
+        objects.insert(location, object.clone());
+        Ok(())
+    }
+
+    async fn get_object_filename(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        filename: &str,
+    ) -> Result<Option<Object>, LsError> {
+        Ok(self
+            .objects
+            .read()
+            .await
+            .get(&location(bucket, key, filename))
+            .cloned())
+    }
+
+    async fn sync_object(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        obj: &mut Object,
+    ) -> Result<bool, LsError> {
+        let mut objects = self.objects.write().await;
+        let Some(stored) = objects.get_mut(&location(bucket, key, &obj.file_name)) else {
+            return Ok(false);
+        };
+
+        obj.preserve(stored.clone());
+        *stored = obj.clone();
+        Ok(true)
+    }
+
+    async fn retain_objects(&self, seen: &[ObjectLocation]) -> Result<u64, LsError> {
+        let seen = seen
+            .iter()
+            .map(|x| location(x.bucket.borrow(), x.key.borrow(), &x.file_name))
+            .collect::<BTreeSet<_>>();
+
+        let mut objects = self.objects.write().await;
+        let before = objects.len();
+        objects.retain(|location, _| seen.contains(location));
+        Ok((before - objects.len()) as u64)
+    }
+
+    async fn visit_objects(
+        &self,
+        visit: &mut (dyn FnMut(ObjectLocation) + Send),
+    ) -> Result<(), LsError> {
+        for ((bucket, key, file_name), _) in self.objects.read().await.iter() {
+            visit(ObjectLocation {
+                bucket: bucket.cloned(),
+                key: key.cloned(),
+                file_name: file_name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn seen_by(&self, bucket: Bucket<'_>, key: Key<'_>, obj: &Object, id: Uuid) {
+        let mut objects = self.objects.write().await;
+        if let Some(stored) = objects.get_mut(&location(bucket, key, &obj.file_name)) {
+            let seen_by = stored.seen_by.get_or_insert_default();
+            let id = id.to_string();
+            if !seen_by.contains(&id) {
+                seen_by.push(id);
+            }
+        }
+    }
+
+    async fn delete_object(&self, bucket: Bucket<'_>, key: Key<'_>, filename: &str) {
+        self.objects
+            .write()
+            .await
+            .remove(&location(bucket, key, filename));
+    }
+
+    async fn set_name(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        file_name: &str,
+        new_name: &str,
+    ) -> Result<u64, LsError> {
+        let from = location(bucket.borrow(), key.borrow(), file_name);
+        let to = location(bucket, key, new_name);
+        relocate(&mut *self.objects.write().await, vec![(from, to)])
+    }
+
+    async fn set_name_bucket(
+        &self,
+        bucket: Bucket<'_>,
+        new_name: Bucket<'_>,
+    ) -> Result<u64, LsError> {
+        let mut objects = self.objects.write().await;
+        let moves = objects
+            .keys()
+            .filter(|(b, _, _)| *b == bucket)
+            .map(|from| {
+                let (_, key, file_name) = from.clone();
+                (from.clone(), (new_name.cloned(), key, file_name))
+            })
+            .collect();
+        relocate(&mut objects, moves)
+    }
+
+    async fn move_object(
+        &self,
+        from: &ObjectLocation,
+        to: &ObjectLocation,
+    ) -> Result<u64, LsError> {
+        let from = location(from.bucket.borrow(), from.key.borrow(), &from.file_name);
+        let to = location(to.bucket.borrow(), to.key.borrow(), &to.file_name);
+        relocate(&mut *self.objects.write().await, vec![(from, to)])
+    }
+
+    async fn move_key(&self, from: &KeyLocation, to: &KeyLocation) -> Result<u64, LsError> {
+        let mut objects = self.objects.write().await;
+        let moves = objects
+            .keys()
+            .filter(|(bucket, key, _)| *bucket == from.bucket && key.in_subtree(&from.key))
+            .map(|location| {
+                let (_, key, file_name) = location.clone();
+                let key = key.rebase(&from.key, &to.key);
+                (location.clone(), (to.bucket.cloned(), key, file_name))
+            })
+            .collect();
+        relocate(&mut objects, moves)
+    }
+
+    async fn delete_bucket(&self, bucket: Bucket<'_>) -> Result<u64, LsError> {
+        let mut objects = self.objects.write().await;
+        let before = objects.len();
+        objects.retain(|(b, _, _), _| *b != bucket);
+        Ok((before - objects.len()) as u64)
+    }
+
+    async fn delete_key(&self, bucket: Bucket<'_>, key: Key<'_>) -> Result<u64, LsError> {
+        let mut objects = self.objects.write().await;
+        let before = objects.len();
+        objects.retain(|(b, k, _), _| *b != bucket || !k.in_subtree(&key));
+        Ok((before - objects.len()) as u64)
+    }
+
+    async fn init_change_log(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    async fn push_change(&self, record: &ChangeRecord) -> Result<(), LsError> {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut changes = self.changes.write().await;
+        changes.push_back(record.clone());
+        while changes.len() > capacity {
+            changes.pop_front();
+        }
+        Ok(())
+    }
+
+    async fn last_changes(&self, limit: usize) -> Result<Vec<ChangeRecord>, LsError> {
+        let changes = self.changes.read().await;
+        Ok(changes
+            .iter()
+            .skip(changes.len().saturating_sub(limit))
+            .cloned()
+            .collect())
+    }
+}
@@ -1,26 +1,35 @@
 pub mod error;
+pub mod memory;
+pub mod sqlite;
 
 use crate::bucket::{
     Bucket, Cowed,
     key::{Key, Segment},
     utils::Changed,
 };
+use futures::TryStreamExt;
 use mongodb::{
     Client, Database, IndexModel,
-    bson::{self, doc, oid::ObjectId},
+    bson::{self, Document, doc, oid::ObjectId},
     options::{ClientOptions, Credential, IndexOptions, ServerAddress},
-    results::{DeleteResult, InsertOneResult, UpdateResult},
+    results::UpdateResult,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tonic::async_trait;
 use uuid::Uuid;
 
-use crate::{bucket::object::Object, state::local_storage::error::LsError};
+use crate::{
+    bucket::object::Object,
+    manager::{KeyLocation, ObjectLocation, change_log::ChangeRecord},
+    state::local_storage::error::LsError,
+};
 
 macro_rules! diff {
     ($t1: expr, $t2: expr $(,$field: ident)+) => {{
         let mut doc = doc!{};
         $(
-            if $t1.$field.change(&$t2.$field) {
+            if $t2.$field.change(&$t1.$field) {
                 doc.insert(concat!("object.", stringify!($field)), bson::to_bson(&$t2.$field).unwrap());
             }
         )+
@@ -29,6 +38,7 @@ macro_rules! diff {
 }
 
 pub const COLLECTION: &str = "objects";
+pub const CHANGES_COLLECTION: &str = "changes";
 
 #[derive(Debug, Serialize)]
 struct AsObjectSerialize<'a> {
@@ -55,6 +65,118 @@ pub struct AsObjectDeserialize {
     pub object: Object,
 }
 
+#[async_trait]
+pub trait MetadataStore: std::fmt::Debug + Send + Sync {
+    async fn new_object(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        object: &Object,
+    ) -> Result<(), LsError>;
+
+    async fn get_object_filename(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        filename: &str,
+    ) -> Result<Option<Object>, LsError>;
+
+    async fn new_objects(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        objects: &[Object],
+    ) -> Result<(), LsError> {
+        for object in objects {
+            self.new_object(bucket.borrow(), key.borrow(), object)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn get_objects(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        file_names: &[String],
+    ) -> Result<Vec<Object>, LsError> {
+        let mut objects = Vec::new();
+        for file_name in file_names {
+            if let Some(object) = self
+                .get_object_filename(bucket.borrow(), key.borrow(), file_name)
+                .await?
+            {
+                objects.push(object);
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn sync_object(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        obj: &mut Object,
+    ) -> Result<bool, LsError>;
+
+    async fn retain_objects(&self, seen: &[ObjectLocation]) -> Result<u64, LsError>;
+
+    async fn visit_objects(
+        &self,
+        visit: &mut (dyn FnMut(ObjectLocation) + Send),
+    ) -> Result<(), LsError>;
+
+    async fn seen_by(&self, bucket: Bucket<'_>, key: Key<'_>, obj: &Object, id: Uuid);
+
+    async fn delete_object(&self, bucket: Bucket<'_>, key: Key<'_>, filename: &str);
+
+    async fn set_name(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        file_name: &str,
+        new_name: &str,
+    ) -> Result<u64, LsError>;
+
+    async fn set_name_bucket(
+        &self,
+        bucket: Bucket<'_>,
+        new_name: Bucket<'_>,
+    ) -> Result<u64, LsError>;
+
+    async fn set_name_key(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        new_name: Segment<'_>,
+    ) -> Result<u64, LsError> {
+        let from = KeyLocation {
+            bucket: bucket.cloned(),
+            key: key.cloned(),
+        };
+        let to = KeyLocation {
+            bucket: bucket.cloned(),
+            key: from.parent().child(new_name.as_ref()),
+        };
+        self.move_key(&from, &to).await
+    }
+
+    async fn move_object(&self, from: &ObjectLocation, to: &ObjectLocation)
+    -> Result<u64, LsError>;
+
+    async fn move_key(&self, from: &KeyLocation, to: &KeyLocation) -> Result<u64, LsError>;
+
+    async fn delete_bucket(&self, bucket: Bucket<'_>) -> Result<u64, LsError>;
+
+    async fn delete_key(&self, bucket: Bucket<'_>, key: Key<'_>) -> Result<u64, LsError>;
+
+    async fn init_change_log(&self, capacity: usize);
+
+    async fn push_change(&self, record: &ChangeRecord) -> Result<(), LsError>;
+
+    async fn last_changes(&self, limit: usize) -> Result<Vec<ChangeRecord>, LsError>;
+}
+
 #[derive(Debug, Default)]
 pub struct LocalStorageBuild {
     password: Option<String>,
@@ -74,54 +196,152 @@ impl LocalStorage {
         self.pool.default_database().unwrap()
     }
 
-    pub async fn sync_object(&self, bucket: Bucket<'_>, key: Key<'_>, obj: &Object) {
-        let db = self.pool.default_database().unwrap();
-        let tmp = db
+    pub async fn get_object_name(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        name: &str,
+    ) -> Result<Option<Object>, LsError> {
+        let tmp = self.pool.default_database().unwrap();
+        let filter = doc! { "bucket": bucket, "key": key, "object.name": name };
+
+        Ok(tmp
             .collection::<AsObjectDeserialize>(COLLECTION)
-            .find_one(doc! {"bucket": bucket.borrow(), "key": key.borrow(), "object.file_name": &obj.file_name})
-            .await
-            .unwrap()
+            .find_one(filter)
+            .await?
+            .map(|x| x.object))
+    }
+
+    async fn update_subtree(
+        &self,
+        filter: Document,
+        update: Vec<Document>,
+    ) -> Result<UpdateResult, LsError> {
+        let objects = self
+            .pool
+            .default_database()
             .unwrap()
-            .object;
+            .collection::<Object>(COLLECTION);
 
-        let to_update = diff!(
-            tmp, obj, file_name, seen_by, taken_by, modified, accessed, created
-        );
+        let mut session = self.pool.start_session().await?;
+        if let Err(er) = session.start_transaction().await {
+            tracing::debug!("[ LocalStorage ] update_subtree without transaction: {er}");
+            return Ok(objects.update_many(filter, update).await?);
+        }
 
-        tracing::warn!("{:?}", to_update);
-        _ = db
-            .collection::<AsObjectSerialize>(COLLECTION)
-            .update_one(
-                doc! {"bucket": bucket, "key": key, "object.file_name": &obj.file_name },
-                doc! {"$set": to_update},
-            )
+        match objects
+            .update_many(filter, update)
+            .session(&mut session)
             .await
-            .unwrap();
+        {
+            Ok(result) => {
+                session.commit_transaction().await?;
+                Ok(result)
+            }
+            Err(er) => {
+                _ = session.abort_transaction().await;
+                Err(er.into())
+            }
+        }
     }
+}
 
-    pub async fn get_object_filename(
+#[async_trait]
+impl MetadataStore for LocalStorage {
+    async fn sync_object(
         &self,
         bucket: Bucket<'_>,
         key: Key<'_>,
-        filename: &str,
-    ) -> Result<Option<AsObjectDeserialize>, LsError> {
-        let tmp = self.pool.default_database().unwrap();
-        let filter = doc! { "bucket": bucket, "key": key, "object.file_name": filename };
+        obj: &mut Object,
+    ) -> Result<bool, LsError> {
+        let db = self.pool.default_database().unwrap();
+        let Some(tmp) = db
+            .collection::<AsObjectDeserialize>(COLLECTION)
+            .find_one(doc! {"bucket": bucket.borrow(), "key": key.borrow(), "object.file_name": &obj.file_name})
+            .await?
+            .map(|x| x.object)
+        else {
+            return Ok(false);
+        };
 
-        Ok(tmp
+        obj.preserve(tmp.clone());
+
+        let to_update = diff!(
+            tmp,
+            obj,
+            file_name,
+            size,
+            checksum,
+            seen_by,
+            taken_by,
+            modified,
+            accessed,
+            created,
+            fingerprint
+        );
+
+        tracing::debug!(
+            "[ LocalStorage ] sync object {}: {to_update:?}",
+            obj.file_name
+        );
+        if !to_update.is_empty() {
+            db.collection::<AsObjectSerialize>(COLLECTION)
+                .update_one(
+                    doc! {"bucket": bucket, "key": key, "object.file_name": &obj.file_name },
+                    doc! {"$set": to_update},
+                )
+                .await?;
+        }
+
+        Ok(true)
+    }
+
+    async fn retain_objects(&self, seen: &[ObjectLocation]) -> Result<u64, LsError> {
+        let pool = self.pool.default_database().unwrap();
+        let seen = seen
+            .iter()
+            .map(|x| (x.bucket.borrow(), x.key.borrow(), x.file_name.as_str()))
+            .collect::<BTreeSet<_>>();
+
+        let mut cursor = pool
             .collection::<AsObjectDeserialize>(COLLECTION)
-            .find_one(filter)
-            .await?)
+            .find(doc! {})
+            .await?;
+
+        let mut objects = Vec::new();
+        while let Some(x) = cursor.try_next().await? {
+            if seen.contains(&(
+                x.bucket.borrow(),
+                x.key.borrow(),
+                x.object.file_name.as_str(),
+            )) {
+                continue;
+            }
+
+            tracing::warn!(
+                "[ LocalStorage ] Object dont found in filesystem: {}/{}/{}",
+                x.bucket,
+                x.key,
+                x.object.file_name
+            );
+            objects.extend(x._id);
+        }
+
+        Ok(pool
+            .collection::<Document>(COLLECTION)
+            .delete_many(doc! {"_id": {"$in": objects}})
+            .await?
+            .deleted_count)
     }
 
-    pub async fn get_object_name(
+    async fn get_object_filename(
         &self,
         bucket: Bucket<'_>,
         key: Key<'_>,
-        name: &str,
+        filename: &str,
     ) -> Result<Option<Object>, LsError> {
         let tmp = self.pool.default_database().unwrap();
-        let filter = doc! { "bucket": bucket, "key": key, "object.name": name };
+        let filter = doc! { "bucket": bucket, "key": key, "object.file_name": filename };
 
         Ok(tmp
             .collection::<AsObjectDeserialize>(COLLECTION)
@@ -130,22 +350,60 @@ impl LocalStorage {
             .map(|x| x.object))
     }
 
-    pub async fn new_object(
+    async fn new_object(
         &self,
         bucket: Bucket<'_>,
         key: Key<'_>,
         object: &Object,
-    ) -> Result<InsertOneResult, LsError> {
+    ) -> Result<(), LsError> {
         let tmp = self.pool.default_database().unwrap();
         let new = AsObjectSerialize::new(bucket, key, object);
 
-        Ok(tmp
-            .collection::<AsObjectSerialize>(COLLECTION)
+        tmp.collection::<AsObjectSerialize>(COLLECTION)
             .insert_one(new)
+            .await?;
+        Ok(())
+    }
+
+    async fn new_objects(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        objects: &[Object],
+    ) -> Result<(), LsError> {
+        if objects.is_empty() {
+            return Ok(());
+        }
+
+        let tmp = self.pool.default_database().unwrap();
+        tmp.collection::<AsObjectSerialize>(COLLECTION)
+            .insert_many(
+                objects
+                    .iter()
+                    .map(|x| AsObjectSerialize::new(bucket.borrow(), key.borrow(), x)),
+            )
+            .ordered(false)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_objects(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        file_names: &[String],
+    ) -> Result<Vec<Object>, LsError> {
+        let tmp = self.pool.default_database().unwrap();
+        Ok(tmp
+            .collection::<AsObjectDeserialize>(COLLECTION)
+            .find(doc! { "bucket": bucket, "key": key, "object.file_name": { "$in": file_names } })
+            .await?
+            .map_ok(|x| x.object)
+            .try_collect::<Vec<_>>()
             .await?)
     }
 
-    pub async fn delete_object(&self, bucket: Bucket<'_>, key: Key<'_>, filename: &str) {
+    async fn delete_object(&self, bucket: Bucket<'_>, key: Key<'_>, filename: &str) {
         let tmp = self.pool.default_database().unwrap();
         _ = tmp
             .collection::<&Object>(COLLECTION)
@@ -153,7 +411,7 @@ impl LocalStorage {
             .await;
     }
 
-    pub async fn seen_by(&self, bucket: Bucket<'_>, key: Key<'_>, obj: &Object, id: Uuid) {
+    async fn seen_by(&self, bucket: Bucket<'_>, key: Key<'_>, obj: &Object, id: Uuid) {
         let tmp = self.pool.default_database().unwrap();
         _ = tmp
             .collection::<AsObjectSerialize>(COLLECTION)
@@ -164,28 +422,29 @@ impl LocalStorage {
             .await;
     }
 
-    pub async fn set_name(
+    async fn set_name(
         &self,
         bucket: Bucket<'_>,
         key: Key<'_>,
         file_name: &str,
         new_name: &str,
-    ) -> Result<UpdateResult, LsError> {
+    ) -> Result<u64, LsError> {
         let tmp = self.pool.default_database().unwrap();
         Ok(tmp
             .collection::<Object>(COLLECTION)
             .update_one(
                 doc! {"bucket": bucket, "key": key, "object.file_name": file_name },
-                doc! { "$set": { "object.name": new_name } },
+                doc! { "$set": { "object.file_name": new_name } },
             )
-            .await?)
+            .await?
+            .modified_count)
     }
 
-    pub async fn set_name_bucket(
+    async fn set_name_bucket(
         &self,
         bucket: Bucket<'_>,
         new_name: Bucket<'_>,
-    ) -> Result<UpdateResult, LsError> {
+    ) -> Result<u64, LsError> {
         let tmp = self.pool.default_database().unwrap();
         Ok(tmp
             .collection::<Object>(COLLECTION)
@@ -193,43 +452,147 @@ impl LocalStorage {
                 doc! {"bucket": bucket },
                 doc! { "$set": { "bucket": new_name } },
             )
-            .await?)
+            .await?
+            .modified_count)
     }
 
-    pub async fn set_name_key(
+    async fn move_object(
         &self,
-        bucket: Bucket<'_>,
-        key: Key<'_>,
-        new_name: Segment<'_>,
-    ) -> Result<UpdateResult, LsError> {
+        from: &ObjectLocation,
+        to: &ObjectLocation,
+    ) -> Result<u64, LsError> {
         let tmp = self.pool.default_database().unwrap();
         Ok(tmp
             .collection::<Object>(COLLECTION)
-            .update_many(
-                doc! {"bucket": bucket, "key": key },
-                doc! { "$set": { "key": new_name } },
+            .update_one(
+                doc! {"bucket": from.bucket.borrow(), "key": from.key.borrow(), "object.file_name": &from.file_name },
+                doc! { "$set": { "bucket": to.bucket.borrow(), "key": to.key.borrow(), "object.file_name": &to.file_name } },
             )
-            .await?)
+            .await?
+            .modified_count)
     }
 
-    pub async fn delete_bucket(&self, bucket: Bucket<'_>) -> Result<DeleteResult, LsError> {
+    async fn move_key(&self, from: &KeyLocation, to: &KeyLocation) -> Result<u64, LsError> {
+        let filter = subtree(from.bucket.borrow(), from.key.borrow());
+        let strip = if from.key.is_root() {
+            0
+        } else {
+            from.key.name().chars().count() as i64 + 1
+        };
+
+        let prefix = if to.key.is_root() {
+            String::new()
+        } else {
+            format!("{}/", to.key.name())
+        };
+
+        let key = doc! {
+            "$cond": [
+                { "$eq": ["$key", from.key.borrow()] },
+                to.key.borrow(),
+                { "$concat": [prefix, { "$substrCP": ["$key", strip, { "$strLenCP": "$key" }] }] },
+            ]
+        };
+
+        Ok(self
+            .update_subtree(
+                filter,
+                vec![doc! { "$set": { "bucket": to.bucket.borrow(), "key": key } }],
+            )
+            .await?
+            .modified_count)
+    }
+
+    async fn delete_bucket(&self, bucket: Bucket<'_>) -> Result<u64, LsError> {
         let tmp = self.pool.default_database().unwrap();
         Ok(tmp
             .collection::<Object>(COLLECTION)
             .delete_many(doc! {"bucket": bucket })
-            .await?)
+            .await?
+            .deleted_count)
+    }
+
+    async fn delete_key(&self, bucket: Bucket<'_>, key: Key<'_>) -> Result<u64, LsError> {
+        let objects = self
+            .pool
+            .default_database()
+            .unwrap()
+            .collection::<Object>(COLLECTION);
+        let filter = subtree(bucket, key);
+
+        let mut session = self.pool.start_session().await?;
+        if let Err(er) = session.start_transaction().await {
+            tracing::debug!("[ LocalStorage ] delete_key without transaction: {er}");
+            return Ok(objects.delete_many(filter).await?.deleted_count);
+        }
+
+        match objects.delete_many(filter).session(&mut session).await {
+            Ok(result) => {
+                session.commit_transaction().await?;
+                Ok(result.deleted_count)
+            }
+            Err(er) => {
+                _ = session.abort_transaction().await;
+                Err(er.into())
+            }
+        }
     }
 
-    pub async fn delete_key(
+    async fn visit_objects(
         &self,
-        bucket: Bucket<'_>,
-        key: Key<'_>,
-    ) -> Result<DeleteResult, LsError> {
+        visit: &mut (dyn FnMut(ObjectLocation) + Send),
+    ) -> Result<(), LsError> {
+        let mut cursor = self
+            .pool
+            .default_database()
+            .unwrap()
+            .collection::<AsObjectDeserialize>(COLLECTION)
+            .find(doc! {})
+            .await?;
+
+        while let Some(x) = cursor.try_next().await? {
+            visit(ObjectLocation {
+                bucket: x.bucket,
+                key: x.key,
+                file_name: x.object.file_name,
+            });
+        }
+        Ok(())
+    }
+
+    async fn init_change_log(&self, capacity: usize) {
+        let db = self.pool.default_database().unwrap();
+        if let Err(er) = db
+            .create_collection(CHANGES_COLLECTION)
+            .capped(true)
+            .size(capacity as u64 * 4096)
+            .max(capacity as u64)
+            .await
+        {
+            tracing::debug!("[ LocalStorage ] create {CHANGES_COLLECTION}: {er}");
+        }
+    }
+
+    async fn push_change(&self, record: &ChangeRecord) -> Result<(), LsError> {
         let tmp = self.pool.default_database().unwrap();
-        Ok(tmp
-            .collection::<Object>(COLLECTION)
-            .delete_many(doc! {"bucket": bucket, "key": key })
-            .await?)
+        tmp.collection::<ChangeRecord>(CHANGES_COLLECTION)
+            .insert_one(record)
+            .await?;
+        Ok(())
+    }
+
+    async fn last_changes(&self, limit: usize) -> Result<Vec<ChangeRecord>, LsError> {
+        let tmp = self.pool.default_database().unwrap();
+        let mut records = tmp
+            .collection::<ChangeRecord>(CHANGES_COLLECTION)
+            .find(doc! {})
+            .sort(doc! { "seq": -1 })
+            .limit(limit as i64)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        records.reverse();
+        Ok(records)
     }
 }
 
@@ -296,3 +659,18 @@ impl LocalStorageBuild {
         ls
     }
 }
+
+fn subtree(bucket: Bucket<'_>, key: Key<'_>) -> Document {
+    if key.is_root() {
+        return doc! { "bucket": bucket };
+    }
+
+    let prefix = format!("^{}/", regex::escape(key.name()));
+    doc! {
+        "bucket": bucket,
+        "$or": [
+            { "key": key },
+            { "key": { "$regex": prefix } },
+        ],
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use futures::TryStreamExt;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use tonic::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    bucket::{Bucket, key::Key, object::Object},
+    manager::{KeyLocation, ObjectLocation, change_log::ChangeRecord},
+    state::local_storage::{MetadataStore, error::LsError},
+};
+
+pub const TABLE_OBJECTS: &str = "objects";
+pub const TABLE_CHANGES: &str = "changes";
+
+#[derive(Debug)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    capacity: AtomicUsize,
+}
+
+impl SqliteStorage {
+    pub async fn new(path: &Path) -> Result<Self, LsError> {
+        let opts = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(opts).await?;
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE_OBJECTS} (
+                bucket TEXT NOT NULL,
+                key TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                object TEXT NOT NULL,
+                PRIMARY KEY (bucket, key, file_name)
+            )"
+        ))
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE_CHANGES} (
+                seq INTEGER PRIMARY KEY,
+                record TEXT NOT NULL
+            )"
+        ))
+        .execute(&pool)
+        .await?;
+
+        tracing::info!("[ SqliteStorage ] Open {path:?}");
+
+        Ok(Self {
+            pool,
+            capacity: AtomicUsize::new(0),
+        })
+    }
+}
+
+fn subtree_prefix(key: &Key<'_>) -> (bool, String) {
+    (key.is_root(), format!("{}/", key.name()))
+}
+
+#[async_trait]
+impl MetadataStore for SqliteStorage {
+    async fn new_object(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        object: &Object,
+    ) -> Result<(), LsError> {
+        sqlx::query(&format!(
+            "INSERT INTO {TABLE_OBJECTS} (bucket, key, file_name, object) VALUES (?1, ?2, ?3, ?4)"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(&object.file_name)
+        .bind(serde_json::to_string(object)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_object_filename(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        filename: &str,
+    ) -> Result<Option<Object>, LsError> {
+        let object = sqlx::query_scalar::<_, String>(&format!(
+            "SELECT object FROM {TABLE_OBJECTS} WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(filename)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(object.map(|x| serde_json::from_str(&x)).transpose()?)
+    }
+
+    async fn sync_object(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        obj: &mut Object,
+    ) -> Result<bool, LsError> {
+        let mut tx = self.pool.begin().await?;
+        let Some(stored) = sqlx::query_scalar::<_, String>(&format!(
+            "SELECT object FROM {TABLE_OBJECTS} WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(&obj.file_name)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(false);
+        };
+
+        obj.preserve(serde_json::from_str(&stored)?);
+
+        sqlx::query(&format!(
+            "UPDATE {TABLE_OBJECTS} SET object = ?4 WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(&obj.file_name)
+        .bind(serde_json::to_string(obj)?)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn retain_objects(&self, seen: &[ObjectLocation]) -> Result<u64, LsError> {
+        let seen = seen
+            .iter()
+            .map(|x| (x.bucket.name(), x.key.name(), x.file_name.as_str()))
+            .collect::<BTreeSet<_>>();
+
+        let mut tx = self.pool.begin().await?;
+        let mut missing = Vec::new();
+        {
+            let query = format!("SELECT bucket, key, file_name FROM {TABLE_OBJECTS}");
+            let mut stored = sqlx::query_as::<_, (String, String, String)>(&query).fetch(&mut *tx);
+
+            while let Some((bucket, key, file_name)) = stored.try_next().await? {
+                if !seen.contains(&(bucket.as_str(), key.as_str(), file_name.as_str())) {
+                    tracing::warn!(
+                        "[ SqliteStorage ] Object dont found in filesystem: {bucket}/{key}/{file_name}"
+                    );
+                    missing.push((bucket, key, file_name));
+                }
+            }
+        }
+
+        let mut deleted = 0;
+        for (bucket, key, file_name) in missing {
+            deleted += sqlx::query(&format!(
+                "DELETE FROM {TABLE_OBJECTS} WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+            ))
+            .bind(&bucket)
+            .bind(&key)
+            .bind(&file_name)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn visit_objects(
+        &self,
+        visit: &mut (dyn FnMut(ObjectLocation) + Send),
+    ) -> Result<(), LsError> {
+        let query = format!("SELECT bucket, key, file_name FROM {TABLE_OBJECTS}");
+        let mut stored = sqlx::query_as::<_, (String, String, String)>(&query).fetch(&self.pool);
+
+        while let Some((bucket, key, file_name)) = stored.try_next().await? {
+            visit(ObjectLocation {
+                bucket: Bucket::new_unchecked(bucket),
+                key: Key::new(key),
+                file_name,
+            });
+        }
+        Ok(())
+    }
+
+    async fn seen_by(&self, bucket: Bucket<'_>, key: Key<'_>, obj: &Object, id: Uuid) {
+        let id = id.to_string();
+        if let Err(er) = sqlx::query(&format!(
+            "UPDATE {TABLE_OBJECTS}
+            SET object = json_set(object, '$.seen_by', json_insert(coalesce(json_extract(object, '$.seen_by'), json('[]')), '$[#]', ?4))
+            WHERE bucket = ?1 AND key = ?2 AND file_name = ?3
+                AND NOT EXISTS (SELECT 1 FROM json_each(object, '$.seen_by') WHERE value = ?4)"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(&obj.file_name)
+        .bind(&id)
+        .execute(&self.pool)
+        .await
+        {
+            tracing::debug!("[ SqliteStorage ] seen_by {}: {er}", obj.file_name);
+        }
+    }
+
+    async fn delete_object(&self, bucket: Bucket<'_>, key: Key<'_>, filename: &str) {
+        _ = sqlx::query(&format!(
+            "DELETE FROM {TABLE_OBJECTS} WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(filename)
+        .execute(&self.pool)
+        .await;
+    }
+
+    async fn set_name(
+        &self,
+        bucket: Bucket<'_>,
+        key: Key<'_>,
+        file_name: &str,
+        new_name: &str,
+    ) -> Result<u64, LsError> {
+        Ok(sqlx::query(&format!(
+            "UPDATE {TABLE_OBJECTS}
+            SET file_name = ?4, object = json_set(object, '$.file_name', ?4)
+            WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+        ))
+        .bind(bucket.name())
+        .bind(key.name())
+        .bind(file_name)
+        .bind(new_name)
+        .execute(&self.pool)
+        .await?
+        .rows_affected())
+    }
+
+    async fn set_name_bucket(
+        &self,
+        bucket: Bucket<'_>,
+        new_name: Bucket<'_>,
+    ) -> Result<u64, LsError> {
+        Ok(sqlx::query(&format!(
+            "UPDATE {TABLE_OBJECTS} SET bucket = ?2 WHERE bucket = ?1"
+        ))
+        .bind(bucket.name())
+        .bind(new_name.name())
+        .execute(&self.pool)
+        .await?
+        .rows_affected())
+    }
+
+    async fn move_object(
+        &self,
+        from: &ObjectLocation,
+        to: &ObjectLocation,
+    ) -> Result<u64, LsError> {
+        Ok(sqlx::query(&format!(
+            "UPDATE {TABLE_OBJECTS}
+            SET bucket = ?4, key = ?5, file_name = ?6, object = json_set(object, '$.file_name', ?6)
+            WHERE bucket = ?1 AND key = ?2 AND file_name = ?3"
+        ))
+        .bind(from.bucket.name())
+        .bind(from.key.name())
+        .bind(&from.file_name)
+        .bind(to.bucket.name())
+        .bind(to.key.name())
+        .bind(&to.file_name)
+        .execute(&self.pool)
+        .await?
+        .rows_affected())
+    }
+
+    async fn move_key(&self, from: &KeyLocation, to: &KeyLocation) -> Result<u64, LsError> {
+        let (root, prefix) = subtree_prefix(&from.key);
+        let strip = if root { 1 } else { prefix.chars().count() + 1 };
+        let to_prefix = if to.key.is_root() {
+            String::new()
+        } else {
+            format!("{}/", to.key.name())
+        };
+
+        Ok(sqlx::query(&format!(
+            "UPDATE {TABLE_OBJECTS}
+            SET bucket = ?6, key = CASE WHEN key = ?3 THEN ?7 ELSE ?8 || substr(key, ?9) END
+            WHERE bucket = ?1 AND (?2 OR key = ?3 OR substr(key, 1, ?4) = ?5)"
+        ))
+        .bind(from.bucket.name())
+        .bind(root)
+        .bind(from.key.name())
+        .bind(prefix.chars().count() as i64)
+        .bind(&prefix)
+        .bind(to.bucket.name())
+        .bind(to.key.name())
+        .bind(&to_prefix)
+        .bind(strip as i64)
+        .execute(&self.pool)
+        .await?
+        .rows_affected())
+    }
+
+    async fn delete_bucket(&self, bucket: Bucket<'_>) -> Result<u64, LsError> {
+        Ok(
+            sqlx::query(&format!("DELETE FROM {TABLE_OBJECTS} WHERE bucket = ?1"))
+                .bind(bucket.name())
+                .execute(&self.pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn delete_key(&self, bucket: Bucket<'_>, key: Key<'_>) -> Result<u64, LsError> {
+        let (root, prefix) = subtree_prefix(&key);
+        Ok(sqlx::query(&format!(
+            "DELETE FROM {TABLE_OBJECTS}
+            WHERE bucket = ?1 AND (?2 OR key = ?3 OR substr(key, 1, ?4) = ?5)"
+        ))
+        .bind(bucket.name())
+        .bind(root)
+        .bind(key.name())
+        .bind(prefix.chars().count() as i64)
+        .bind(&prefix)
+        .execute(&self.pool)
+        .await?
+        .rows_affected())
+    }
+
+    async fn init_change_log(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    async fn push_change(&self, record: &ChangeRecord) -> Result<(), LsError> {
+        let capacity = self.capacity.load(Ordering::Relaxed) as i64;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {TABLE_CHANGES} (seq, record) VALUES (?1, ?2)"
+        ))
+        .bind(record.seq as i64)
+        .bind(serde_json::to_string(record)?)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!(
+            "DELETE FROM {TABLE_CHANGES} WHERE seq <= (SELECT MAX(seq) FROM {TABLE_CHANGES}) - ?1"
+        ))
+        .bind(capacity)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn last_changes(&self, limit: usize) -> Result<Vec<ChangeRecord>, LsError> {
+        let mut records = sqlx::query_scalar::<_, String>(&format!(
+            "SELECT record FROM {TABLE_CHANGES} ORDER BY seq DESC LIMIT ?1"
+        ))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|x| serde_json::from_str(&x))
+        .collect::<Result<Vec<ChangeRecord>, _>>()?;
+
+        records.reverse();
+        Ok(records)
+    }
+}
@@ -2,9 +2,14 @@ pub mod local_storage;
 
 use crate::{
     actor::Actor,
-    bucket::{Bucket, bucket_map::BucketMap, key::Key},
-    grpc_v1::Permissions,
-    manager::Manager,
+    bucket::{Bucket, Cowed, bucket_map::BucketMap, key::Key, progress::BuildProgress},
+    grpc_v1::{ConnectionAuthMS, Permissions, UserReply},
+    manager::{
+        Manager, ManagerAsk, ManagerMessage, ManagerReply,
+        change_log::ChangeLog,
+        reconcile::Job,
+        websocket::{WebSocketConfig, WebSocketHandler, protocol::Subscription},
+    },
 };
 use hyper_tungstenite::HyperWebsocket;
 use std::sync::Arc;
@@ -12,12 +17,16 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 pub struct State {
-    tree: Arc<RwLock<BucketMap>>,
+    tree: Arc<BucketMap>,
     ref_manager: <Manager as Actor>::ActorRef,
+    change_log: Arc<RwLock<ChangeLog>>,
+    auth: ConnectionAuthMS,
+    ws_config: WebSocketConfig,
+    progress: Arc<BuildProgress>,
 }
 
 impl std::ops::Deref for State {
-    type Target = Arc<RwLock<BucketMap>>;
+    type Target = Arc<BucketMap>;
     fn deref(&self) -> &Self::Target {
         &self.tree
     }
@@ -25,27 +34,97 @@ impl std::ops::Deref for State {
 
 impl State {
     pub async fn new(
-        tree: Arc<RwLock<BucketMap>>,
+        tree: Arc<BucketMap>,
         ref_manager: <Manager as Actor>::ActorRef,
+        change_log: Arc<RwLock<ChangeLog>>,
+        auth: ConnectionAuthMS,
+        ws_config: WebSocketConfig,
+        progress: Arc<BuildProgress>,
     ) -> Self {
-        Self { tree, ref_manager }
+        Self {
+            tree,
+            ref_manager,
+            change_log,
+            auth,
+            ws_config,
+            progress,
+        }
+    }
+
+    pub fn progress(&self) -> &BuildProgress {
+        &self.progress
     }
 
     pub async fn bucket(
         &self,
         user_id: Uuid,
-        bucket_name: String,
+        bucket_name: Option<&str>,
         permission: Permissions,
-    ) -> Result<bool, String> {
-        todo!()
+    ) -> bool {
+        self.auth
+            .is_allowed(user_id, bucket_name, permission)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn user(&self, user_id: Uuid) -> Option<Arc<UserReply>> {
+        self.auth.buckets_user(user_id).await
+    }
+
+    pub async fn reconcile(&self, fix: bool) -> Option<Uuid> {
+        match self
+            .ref_manager
+            .ask(ManagerMessage::Reconcile { fix })
+            .await
+        {
+            ManagerReply::Reconciling(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub async fn reconcile_job(&self, id: Uuid) -> Option<Job> {
+        match self
+            .ref_manager
+            .ask(ManagerMessage::Ask(ManagerAsk::Reconcile(id)))
+            .await
+        {
+            ManagerReply::Reconciled(job) => job,
+            _ => None,
+        }
     }
 
     pub async fn add_client(
         &self,
+        user_id: Uuid,
         bucket: Option<Bucket<'_>>,
         key: Option<Key<'_>>,
+        resume_from: Option<u64>,
         sender: HyperWebsocket,
     ) {
-        todo!()
+        let subscription = Subscription::new(bucket.map(Cowed::owned), key.map(Cowed::owned));
+        let tree = self.tree.clone();
+        let change_log = self.change_log.clone();
+        let auth = self.auth.clone();
+        let config = self.ws_config;
+
+        tokio::spawn(async move {
+            match sender.await {
+                Ok(ws) => {
+                    WebSocketHandler::new(
+                        ws,
+                        tree,
+                        change_log,
+                        auth,
+                        user_id,
+                        config,
+                        Some((subscription, resume_from)),
+                    )
+                    .start();
+                }
+                Err(er) => {
+                    tracing::error!("[ State ] add client error: {er}");
+                }
+            }
+        });
     }
 }